@@ -0,0 +1,49 @@
+use crate::escape_md;
+use llm_client::LlmError;
+
+const DEFAULT_ERROR_REPLY: &str = "Something went wrong, sorry...";
+
+/// Build a user facing message (escaped markdown) describing why the request failed.
+pub fn error_reply(error: &eyre::Report) -> String {
+    let Some(error) = error.downcast_ref::<LlmError>() else {
+        return escape_md(DEFAULT_ERROR_REPLY);
+    };
+
+    let reply = match error {
+        LlmError::Unauthorized { .. } | LlmError::UnknownModel { .. } => {
+            "The bot can't access its language model right now. Please let the bot owner know."
+        }
+        LlmError::RateLimited { .. } => {
+            "I'm getting too many requests right now. Please try again in a minute."
+        }
+        LlmError::Server { .. } => {
+            "The language model service is having problems. Please try again later."
+        }
+        LlmError::DeadlineExceeded { .. } => {
+            "The language model is taking too long to respond. Please try again later."
+        }
+        LlmError::Transport(_) => {
+            "I couldn't reach the language model service. Please try again later."
+        }
+        LlmError::MalformedResponse { .. }
+        | LlmError::InvalidStructuredOutput { .. }
+        | LlmError::ToolRoundsExceeded { .. }
+        | LlmError::UnexpectedEmbeddingsCount { .. }
+        | LlmError::EmptyChoices
+        | LlmError::EmptyModerationResults => {
+            "The language model returned an unexpected response. Please try again."
+        }
+        LlmError::ContextWindowExceeded { .. } => {
+            "Your message is too long for me. Please send a shorter one."
+        }
+        LlmError::Truncated { .. } => {
+            "The response got too long and was cut off. Please try a shorter message."
+        }
+        LlmError::UnexpectedStatus { .. }
+        | LlmError::UnrecordedRequest { .. }
+        | LlmError::CassetteMismatch { .. }
+        | LlmError::CassetteIo { .. } => DEFAULT_ERROR_REPLY,
+    };
+
+    escape_md(reply)
+}
@@ -1,9 +1,21 @@
-use crate::{escape_md, EditOrSend, MessageHandlerContext, TgBot};
+use crate::{
+    error_reply, escape_md, ContentSource, EditOrSend, MessageHandlerContext, ModerationAction,
+    TgBot,
+};
+use futures::StreamExt;
+use llm_client::ChatMessage;
+use std::time::{Duration, Instant};
 use teloxide::{types::Message as TgMessage, RequestError};
 
 const DEFAULT_REPLY: &str =
     r"Unexpected input. You can send me a note or ask for help (for example type /help).";
 
+const REFUSED_HELP_REPLY: &str = "Sorry, I can't help with this.";
+
+/// Minimal interval between edits of the message while the help is being generated.
+/// Telegram starts rejecting edits if they are sent more often than about once per second.
+const STREAM_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
 pub async fn handle_help(
     ctx: &MessageHandlerContext,
     bot: &TgBot,
@@ -19,21 +31,99 @@ pub async fn handle_help(
         return Ok(());
     };
 
-    let bot_msg = bot
+    let mut bot_msg = bot
         .edit_or_reply(user_msg, bot_msg, r"*Generating help message\.\.\.* ")
         .await?;
 
-    let response = match ctx.help_generator.generate_help(text).await {
-        Ok(response) => response,
+    let chat_id = user_msg.chat.id;
+    let mut session = ctx.sessions.load(chat_id);
+
+    let mut responses = match ctx.help_generator.stream_help(&session, text).await {
+        Ok(responses) => responses,
         Err(e) => {
             log::warn!("Failed to generate help: {e}");
-            bot.edit(bot_msg, r"Something went wrong, sorry\.\.\.")
-                .await?;
+            bot.edit(bot_msg, error_reply(&e)).await?;
             return Ok(());
         }
     };
 
-    bot.edit(bot_msg, escape_md(&response)).await?;
+    // Moderated help is shown only after it's checked as a whole, so content the policy refuses
+    // never appears in the chat.
+    let show_progress = ctx.moderation.is_none();
+
+    let mut response = String::new();
+    let mut truncated = false;
+    let mut error = None;
+    let mut last_edit = Instant::now();
+
+    while let Some(item) = responses.next().await {
+        match item {
+            Ok(item) => {
+                response = item.text;
+                truncated = item.truncated;
+            }
+            Err(e) => {
+                log::warn!("Help generation interrupted: {e}");
+                error = Some(e);
+                break;
+            }
+        }
+
+        if !show_progress || response.is_empty() || last_edit.elapsed() < STREAM_EDIT_INTERVAL {
+            continue;
+        }
+
+        match bot
+            .edit(bot_msg.clone(), format!("{}…", escape_md(&response)))
+            .await
+        {
+            Ok(msg) => bot_msg = msg,
+            Err(e) => log::warn!("Failed to update help message: {e}"),
+        }
+        last_edit = Instant::now();
+    }
+
+    if response.is_empty() {
+        let reply = match error {
+            Some(e) => error_reply(&e),
+            None => r"Something went wrong, sorry\.\.\.".to_string(),
+        };
+        bot.edit(bot_msg, reply).await?;
+        return Ok(());
+    }
+
+    let verdict = ctx
+        .moderate(user_msg, ContentSource::HelpReply, &response)
+        .await;
+    let mut reply = match verdict.action {
+        ModerationAction::Refuse => {
+            bot.edit(bot_msg, escape_md(REFUSED_HELP_REPLY)).await?;
+            return Ok(());
+        }
+        ModerationAction::Warn | ModerationAction::Sensitive => format!(
+            "{}\n\n_{}_",
+            escape_md(&response),
+            escape_md(&verdict.warning())
+        ),
+        ModerationAction::Allow => escape_md(&response),
+    };
+
+    // Show the part that was generated before the failure, followed by the explanation.
+    if let Some(e) = error {
+        reply = format!("{reply}\n\n_{}_", error_reply(&e));
+        bot.edit(bot_msg, reply).await?;
+        return Ok(());
+    }
+
+    bot.edit(bot_msg, reply).await?;
+
+    // only complete replies are remembered, so the model doesn't continue broken ones
+    if truncated {
+        return Ok(());
+    }
+    session.push(ChatMessage::user(text.trim()));
+    session.push(ChatMessage::assistant(response));
+    ctx.sessions.save(chat_id, &session);
 
     Ok(())
 }
@@ -1,78 +1,319 @@
-use crate::{BotArgs, EditOrSend, HelpGenerator, TagsGenerator, TaskSelector, TaskType};
+use crate::{
+    build_llm_cache, build_llm_client, build_moderator, build_rate_limiter, build_transcriber,
+    escape_md, with_usage_key, BotArgs, ContentSource, EditOrSend, HelpGenerator, ModerationAction,
+    ModerationStage, ModerationVerdict, TagsGenerator, TaskSelection, TaskSelector, TaskType,
+    UsageBudget, UsageKey, UsageLedger, UsageTrackingClient,
+};
+use llm_client::{CachingClient, LlmClient, Priority, RateLimiter, SessionStore, Transcriber};
+use std::sync::Arc;
 use teloxide::{
-    adaptors::DefaultParseMode, requests::Requester, types::Message as TgMessage, Bot, RequestError,
+    adaptors::DefaultParseMode,
+    requests::Requester,
+    types::{CallbackQuery, ChatId, Message as TgMessage, UserId},
+    Bot, RequestError,
 };
 
+pub use error_reply::*;
 pub use help::*;
 pub use note::*;
+pub use photo::*;
+pub use task_choice::*;
+pub use usage::*;
+pub use voice::*;
 
+mod error_reply;
 mod help;
 mod note;
+mod photo;
+mod task_choice;
+mod usage;
+mod voice;
 
 pub type TgBot = DefaultParseMode<Bot>;
 
+pub(crate) const BUDGET_EXCEEDED_REPLY: &str =
+    "You've used up your daily limit of the language model. Please try again tomorrow.";
+
+pub(crate) const REFUSED_REPLY: &str = "Sorry, I can't process this message.";
+
 pub struct MessageHandlerContext {
+    pub llm_client: Arc<dyn LlmClient>,
+    pub usage_ledger: Arc<UsageLedger>,
+    pub usage_budget: UsageBudget,
     pub tags_generator: TagsGenerator,
     pub task_selector: TaskSelector,
     pub help_generator: HelpGenerator,
+    /// Limiter of the main backend, `None` if no limits are set.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Cache of completions, `None` if it's disabled.
+    pub llm_cache: Option<Arc<CachingClient>>,
+    /// Conversations with the help generator by chat id.
+    pub sessions: SessionStore,
+    /// Speech recognition of voice messages, `None` if it's not set up.
+    pub transcriber: Option<Arc<dyn Transcriber>>,
+    /// Check of user messages and generated help, `None` if moderation is off.
+    pub moderation: Option<ModerationStage>,
+    /// Tasks selected with lower confidence are not done, the user is asked to choose instead.
+    pub task_confidence_threshold: f32,
+    /// Messages waiting for the user to choose the task.
+    pub pending_tasks: PendingTasks,
 }
 
 impl MessageHandlerContext {
-    pub fn new(args: &BotArgs) -> Self {
-        let tags_generator = TagsGenerator::new(&args.secrets.mistral_token)
+    pub fn new(args: &BotArgs) -> eyre::Result<Self> {
+        // connections are pooled by the client, so all APIs share a single one
+        let http_client = args.http.llm_http_settings()?.build()?;
+        let rate_limiter = build_rate_limiter(args);
+        let llm_client = build_llm_client(args, &http_client, rate_limiter.as_ref())?;
+        let llm_cache = build_llm_cache(args, llm_client.clone());
+        let llm_client: Arc<dyn LlmClient> = match llm_cache.clone() {
+            Some(llm_cache) => llm_cache,
+            None => llm_client,
+        };
+        let moderation =
+            build_moderator(args, &http_client, rate_limiter.as_ref())?.map(|moderator| {
+                ModerationStage::new(moderator)
+                    .with_policy(args.moderation.moderation_actions.clone())
+                    .with_audit_log(args.moderation.moderation_audit_log.clone())
+            });
+
+        Ok(Self {
+            rate_limiter,
+            llm_cache,
+            transcriber: build_transcriber(args, &http_client),
+            moderation,
+            ..Self::with_llm_client(args, llm_client)
+        })
+    }
+
+    /// Create a context that uses the given client for all generators.
+    /// Usage of the client is recorded to the ledger of the context.
+    pub fn with_llm_client(args: &BotArgs, llm_client: Arc<dyn LlmClient>) -> Self {
+        let usage_ledger = Arc::new(UsageLedger::new());
+        let llm_client: Arc<dyn LlmClient> = Arc::new(UsageTrackingClient::new(
+            llm_client,
+            usage_ledger.clone(),
+            args.usage.llm_prices.clone(),
+        ));
+
+        // users wait for replies of all generators, only summaries of long notes are sent
+        // in the background (see `TruncationStrategy::SummarizeChunks`)
+        let mut tags_generator = TagsGenerator::new(llm_client.clone())
+            .with_priority(Priority::Interactive)
             .with_temperature(args.default_temperature)
             .with_random_seed(args.random_seed);
-        let task_selector = TaskSelector::new(&args.secrets.mistral_token)
+        if let Some(model) = &args.models.tags_generator_model {
+            tags_generator = tags_generator.with_model(model);
+        }
+        tags_generator = tags_generator.with_vision_model(&args.models.vision_model);
+        let mut task_selector = TaskSelector::new(llm_client.clone())
+            .with_priority(Priority::Interactive)
             .with_temperature(args.default_temperature)
-            .with_random_seed(args.random_seed);
-        let help_generator = HelpGenerator::new(&args.secrets.mistral_token)
+            .with_random_seed(args.random_seed)
+            .with_samples(args.task_selection.task_selector_samples);
+        if let Some(model) = &args.models.task_selector_model {
+            task_selector = task_selector.with_model(model);
+        }
+        let mut help_generator = HelpGenerator::new(llm_client.clone())
+            .with_priority(Priority::Interactive)
             .with_temperature(args.default_temperature)
             .with_random_seed(args.random_seed);
+        if let Some(model) = &args.models.help_generator_model {
+            help_generator = help_generator.with_model(model);
+        }
 
         Self {
+            llm_client,
+            usage_ledger,
+            usage_budget: args.usage.budget(),
             tags_generator,
             task_selector,
             help_generator,
+            rate_limiter: None,
+            llm_cache: None,
+            sessions: args.session.session_store(),
+            transcriber: None,
+            moderation: None,
+            task_confidence_threshold: args.task_selection.task_confidence_threshold,
+            pending_tasks: PendingTasks::new(),
         }
     }
 
+    /// Check that models of all generators are available.
+    pub async fn validate_models(&self) -> eyre::Result<()> {
+        self.task_selector
+            .validate_model()
+            .await
+            .map_err(|e| eyre::eyre!("task selector: {e}"))?;
+        self.tags_generator
+            .validate_model()
+            .await
+            .map_err(|e| eyre::eyre!("tags generator: {e}"))?;
+        // text notes still work without it, so the bot is started anyway
+        if let Err(e) = self.tags_generator.validate_vision_model().await {
+            log::warn!("Vision model is not available, photos can't be tagged: {e}");
+        }
+        self.help_generator
+            .validate_model()
+            .await
+            .map_err(|e| eyre::eyre!("help generator: {e}"))?;
+
+        Ok(())
+    }
+
     pub async fn handle_message(
         &self,
         bot: &TgBot,
         user_msg: TgMessage,
     ) -> Result<(), RequestError> {
         let chat_id = user_msg.chat.id;
-        let Some(text) = user_msg.text() else {
-            bot.send_message(chat_id, "I can only process text messages")
-                .await?;
+        let text = user_msg.text();
+        let is_voice = user_msg.voice().is_some() || user_msg.audio().is_some();
+        if text.is_none() && user_msg.photo().is_none() && !is_voice {
+            bot.send_message(
+                chat_id,
+                "I can only process text messages, voice messages and photos",
+            )
+            .await?;
             return Ok(());
-        };
+        }
 
         log::debug!("Received message: {:?}", user_msg);
-        let loading_message = bot.reply(&user_msg, r"*Processing message\.\.\.* ").await?;
 
-        let task_type = match self.task_selector.select_task(text).await {
-            Ok(task_type) => task_type,
+        let key = UsageKey {
+            user_id: user_msg.from().map(|user| user.id),
+            chat_id,
+        };
+
+        if text.is_some_and(is_usage_command) {
+            return handle_usage(self, bot, &user_msg, key).await;
+        }
+
+        if key
+            .user_id
+            .is_some_and(|user_id| self.is_budget_exceeded(user_id))
+        {
+            bot.reply(&user_msg, escape_md(BUDGET_EXCEEDED_REPLY))
+                .await?;
+            return Ok(());
+        }
+
+        match text {
+            Some(text) => with_usage_key(key, self.process_message(bot, &user_msg, text)).await,
+            None if is_voice => with_usage_key(key, handle_voice(self, bot, &user_msg)).await,
+            // photos are always notes, their captions are only hints
+            None => with_usage_key(key, handle_photo(self, bot, &user_msg)).await,
+        }
+    }
+
+    /// Whether the user has used up the daily budget and must not make more LLM requests.
+    pub fn is_budget_exceeded(&self, user_id: UserId) -> bool {
+        let exceeded = self
+            .usage_ledger
+            .is_budget_exceeded(user_id, &self.usage_budget);
+        if exceeded {
+            log::info!("User {user_id} has exceeded the daily budget");
+        }
+
+        exceeded
+    }
+
+    /// Check the content related to the message, everything is allowed if moderation is off.
+    pub async fn moderate(
+        &self,
+        user_msg: &TgMessage,
+        source: ContentSource,
+        text: &str,
+    ) -> ModerationVerdict {
+        match &self.moderation {
+            Some(moderation) => moderation.check(user_msg, source, text).await,
+            None => ModerationVerdict::allow(),
+        }
+    }
+
+    /// Select what to do with the message and do it.
+    async fn process_message(
+        &self,
+        bot: &TgBot,
+        user_msg: &TgMessage,
+        text: &str,
+    ) -> Result<(), RequestError> {
+        let loading_message = bot.reply(user_msg, r"*Processing message\.\.\.* ").await?;
+
+        let verdict = self
+            .moderate(user_msg, ContentSource::UserMessage, text)
+            .await;
+        match verdict.action {
+            ModerationAction::Refuse => {
+                bot.edit(loading_message, escape_md(REFUSED_REPLY)).await?;
+                return Ok(());
+            }
+            ModerationAction::Warn => {
+                bot.reply(user_msg, escape_md(&verdict.warning())).await?;
+            }
+            ModerationAction::Allow | ModerationAction::Sensitive => {}
+        }
+
+        let selection = match self.task_selector.select_task_with_confidence(text).await {
+            Ok(selection) => selection,
             Err(e) => {
                 log::warn!("Failed to select task: {}", e);
-                bot.edit(loading_message, r"Something went wrong, sorry\.\.\.")
-                    .await?;
+                bot.edit(loading_message, error_reply(&e)).await?;
                 return Ok(());
             }
         };
 
-        match task_type {
+        let TaskSelection { task, confidence } = selection;
+        if confidence < self.task_confidence_threshold {
+            log::info!("Task {task} selected with low confidence {confidence:.2}, asking user");
+            return ask_task(self, bot, user_msg, text, &verdict, loading_message).await;
+        }
+
+        self.run_task(bot, user_msg, text, &verdict, task, loading_message)
+            .await
+    }
+
+    /// Do the selected task, replacing the bot message with the result.
+    pub async fn run_task(
+        &self,
+        bot: &TgBot,
+        user_msg: &TgMessage,
+        text: &str,
+        verdict: &ModerationVerdict,
+        task: TaskType,
+        bot_msg: TgMessage,
+    ) -> Result<(), RequestError> {
+        match task {
             TaskType::Note => {
-                handle_note(self, bot, &user_msg, text, loading_message).await?;
+                handle_note(self, bot, user_msg, text, verdict, bot_msg).await?;
             }
             TaskType::Help => {
-                handle_help(self, bot, &user_msg, text, loading_message).await?;
+                handle_help(self, bot, user_msg, text, bot_msg).await?;
             }
             TaskType::Unknown => {
-                handle_help(self, bot, &user_msg, None, loading_message).await?;
+                handle_help(self, bot, user_msg, None, bot_msg).await?;
             }
         }
 
         Ok(())
     }
+
+    /// Handle a press of an inline button.
+    pub async fn handle_callback_query(
+        &self,
+        bot: &TgBot,
+        query: CallbackQuery,
+    ) -> Result<(), RequestError> {
+        log::debug!("Received callback query: {:?}", query);
+
+        let key = UsageKey {
+            user_id: Some(query.from.id),
+            chat_id: query
+                .message
+                .as_ref()
+                .map_or(ChatId(query.from.id.0 as i64), |msg| msg.chat.id),
+        };
+
+        with_usage_key(key, handle_task_choice(self, bot, query)).await
+    }
 }
@@ -1,4 +1,6 @@
-use crate::{EditOrSend, MessageHandlerContext, TgBot};
+use crate::{
+    error_reply, EditOrSend, MessageHandlerContext, ModerationAction, ModerationVerdict, TgBot,
+};
 use teloxide::{types::Message as TgMessage, RequestError};
 
 pub async fn handle_note(
@@ -6,6 +8,7 @@ pub async fn handle_note(
     bot: &TgBot,
     user_msg: &TgMessage,
     text: &str,
+    verdict: &ModerationVerdict,
     bot_msg: impl Into<Option<TgMessage>>,
 ) -> Result<(), RequestError> {
     let bot_msg: Option<TgMessage> = bot_msg.into();
@@ -16,11 +19,11 @@ pub async fn handle_note(
 
     log::debug!("processing tags");
     let tags = match ctx.tags_generator.generate_tags_md(text).await {
+        Ok(tags) if verdict.action == ModerationAction::Sensitive => format!(r"{tags} \#sensitive"),
         Ok(tags) => tags,
         Err(e) => {
             log::warn!("Failed to generate tags: {}", e);
-            bot.edit(bot_msg, r"Something went wrong, sorry\.\.\.")
-                .await?;
+            bot.edit(bot_msg, error_reply(&e)).await?;
             return Ok(());
         }
     };
@@ -0,0 +1,86 @@
+use crate::{
+    download_file, error_reply, escape_md, ContentSource, EditOrSend, MessageHandlerContext,
+    ModerationAction, ModerationVerdict, TgBot, REFUSED_REPLY,
+};
+use llm_client::ImageUrl;
+use teloxide::{types::Message as TgMessage, RequestError};
+
+/// Largest side of the photo sent to the model.
+/// Bigger photos take more tokens and don't improve the tags.
+const MAX_PHOTO_SIDE: u32 = 1280;
+
+const DOWNLOAD_FAILED_REPLY: &str = "Failed to download the photo, please try again.";
+
+pub async fn handle_photo(
+    ctx: &MessageHandlerContext,
+    bot: &TgBot,
+    user_msg: &TgMessage,
+) -> Result<(), RequestError> {
+    // Telegram sends every photo in several sizes, from the smallest to the largest
+    let Some(sizes) = user_msg.photo() else {
+        return Ok(());
+    };
+    let Some(photo) = sizes
+        .iter()
+        .rev()
+        .find(|size| size.width.max(size.height) <= MAX_PHOTO_SIDE)
+        .or(sizes.first())
+    else {
+        return Ok(());
+    };
+
+    let bot_msg = bot.reply(user_msg, r"*Generating tags\.\.\.* ").await?;
+
+    let verdict = match user_msg.caption() {
+        Some(caption) => {
+            ctx.moderate(user_msg, ContentSource::UserMessage, caption)
+                .await
+        }
+        None => ModerationVerdict::allow(),
+    };
+    match verdict.action {
+        ModerationAction::Refuse => {
+            bot.edit(bot_msg, escape_md(REFUSED_REPLY)).await?;
+            return Ok(());
+        }
+        ModerationAction::Warn => {
+            bot.reply(user_msg, escape_md(&verdict.warning())).await?;
+        }
+        ModerationAction::Allow | ModerationAction::Sensitive => {}
+    }
+
+    let image = match download_image(bot, &photo.file.id).await {
+        Ok(image) => image,
+        Err(e) => {
+            log::warn!("Failed to download photo: {e}");
+            bot.edit(bot_msg, escape_md(DOWNLOAD_FAILED_REPLY)).await?;
+            return Ok(());
+        }
+    };
+
+    log::debug!("processing photo tags");
+    let tags = match ctx
+        .tags_generator
+        .generate_image_tags_md(image, user_msg.caption())
+        .await
+    {
+        Ok(tags) if verdict.action == ModerationAction::Sensitive => format!(r"{tags} \#sensitive"),
+        Ok(tags) => tags,
+        Err(e) => {
+            log::warn!("Failed to generate photo tags: {}", e);
+            bot.edit(bot_msg, error_reply(&e)).await?;
+            return Ok(());
+        }
+    };
+
+    bot.edit(bot_msg, tags).await?;
+
+    Ok(())
+}
+
+async fn download_image(bot: &TgBot, file_id: &str) -> Result<ImageUrl, RequestError> {
+    let data = download_file(bot, file_id).await?;
+
+    // Telegram converts photos to JPEG
+    Ok(ImageUrl::from_bytes("image/jpeg", &data))
+}
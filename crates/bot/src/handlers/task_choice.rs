@@ -0,0 +1,174 @@
+use crate::{
+    escape_md, EditOrSend, MessageHandlerContext, ModerationVerdict, TaskType, TgBot,
+    BUDGET_EXCEEDED_REPLY,
+};
+use enum_iterator::all;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters},
+    requests::Requester,
+    types::{
+        CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message as TgMessage,
+        MessageId,
+    },
+    RequestError,
+};
+
+/// How long the user can answer the question, unanswered questions are forgotten after it.
+const PENDING_TASK_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Prefix of the callback data of the buttons, followed by the task.
+const CALLBACK_PREFIX: &str = "task:";
+
+const ASK_TASK_REPLY: &str =
+    "I'm not sure what to do with this message. Should I save it as a note or is it a question about the bot?";
+
+const EXPIRED_REPLY: &str = "This question has expired, please send the message again.";
+
+const NOT_AUTHOR_REPLY: &str = "Only the author of the message can choose.";
+
+/// Message waiting for the user to choose the task.
+#[derive(Debug, Clone)]
+pub struct PendingTask {
+    pub user_msg: TgMessage,
+    /// Text of the message, or transcript of a voice message.
+    pub text: String,
+    pub verdict: ModerationVerdict,
+    created_at: Instant,
+}
+
+/// Messages the bot asked about, by the chat and id of the question.
+#[derive(Debug, Default)]
+pub struct PendingTasks {
+    tasks: Mutex<HashMap<(ChatId, MessageId), PendingTask>>,
+}
+
+impl PendingTasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember the message asked about with the question. Expired questions are dropped.
+    pub fn insert(
+        &self,
+        question: &TgMessage,
+        user_msg: &TgMessage,
+        text: &str,
+        verdict: &ModerationVerdict,
+    ) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.retain(|_, task| task.created_at.elapsed() < PENDING_TASK_TTL);
+        tasks.insert(
+            (question.chat.id, question.id),
+            PendingTask {
+                user_msg: user_msg.clone(),
+                text: text.to_string(),
+                verdict: verdict.clone(),
+                created_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Take the message asked about with the question, `None` if it's unknown or expired.
+    pub fn take(&self, question: &TgMessage) -> Option<PendingTask> {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks
+            .remove(&(question.chat.id, question.id))
+            .filter(|task| task.created_at.elapsed() < PENDING_TASK_TTL)
+    }
+
+    /// Put back a task taken by mistake, keeping its age.
+    fn restore(&self, question: &TgMessage, task: PendingTask) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.insert((question.chat.id, question.id), task);
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Ask the user what to do with the message, instead of guessing.
+pub async fn ask_task(
+    ctx: &MessageHandlerContext,
+    bot: &TgBot,
+    user_msg: &TgMessage,
+    text: &str,
+    verdict: &ModerationVerdict,
+    bot_msg: TgMessage,
+) -> Result<(), RequestError> {
+    let buttons =
+        [(TaskType::Note, "Save note"), (TaskType::Help, "Get help")].map(|(task, label)| {
+            InlineKeyboardButton::callback(label, format!("{CALLBACK_PREFIX}{task}"))
+        });
+
+    let question = bot
+        .edit_message_text(bot_msg.chat.id, bot_msg.id, escape_md(ASK_TASK_REPLY))
+        .reply_markup(InlineKeyboardMarkup::new([buttons]))
+        .await?;
+    ctx.pending_tasks.insert(&question, user_msg, text, verdict);
+
+    Ok(())
+}
+
+/// Do the task the user chose with a button of [`ask_task`].
+pub async fn handle_task_choice(
+    ctx: &MessageHandlerContext,
+    bot: &TgBot,
+    query: CallbackQuery,
+) -> Result<(), RequestError> {
+    let task = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(CALLBACK_PREFIX))
+        .and_then(|task| all::<TaskType>().find(|t| t.to_string() == task));
+    let (Some(task), Some(question)) = (task, query.message) else {
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+
+    let Some(pending) = ctx.pending_tasks.take(&question) else {
+        bot.answer_callback_query(query.id).await?;
+        bot.edit(question, escape_md(EXPIRED_REPLY)).await?;
+        return Ok(());
+    };
+
+    // in groups, others can see the buttons too
+    if pending.user_msg.from().map(|user| user.id) != Some(query.from.id) {
+        ctx.pending_tasks.restore(&question, pending);
+        bot.answer_callback_query(query.id)
+            .text(NOT_AUTHOR_REPLY)
+            .await?;
+        return Ok(());
+    }
+
+    // the question stays, it can still be answered if the budget is renewed before it expires
+    if ctx.is_budget_exceeded(query.from.id) {
+        ctx.pending_tasks.restore(&question, pending);
+        bot.answer_callback_query(query.id)
+            .text(BUDGET_EXCEEDED_REPLY)
+            .await?;
+        return Ok(());
+    }
+
+    bot.answer_callback_query(query.id).await?;
+    log::debug!("User chose task {task} for message {}", pending.user_msg.id);
+
+    ctx.run_task(
+        bot,
+        &pending.user_msg,
+        &pending.text,
+        &pending.verdict,
+        task,
+        question,
+    )
+    .await
+}
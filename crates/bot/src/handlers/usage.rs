@@ -0,0 +1,88 @@
+use crate::{escape_md, EditOrSend, MessageHandlerContext, TgBot, UsageKey, UsageStats};
+use teloxide::{types::Message as TgMessage, RequestError};
+
+pub const USAGE_COMMAND: &str = "/usage";
+
+/// Whether the text is the usage command (optionally addressed to the bot, e.g. `/usage@bot`).
+pub fn is_usage_command(text: &str) -> bool {
+    text.split_whitespace()
+        .next()
+        .and_then(|command| command.strip_prefix(USAGE_COMMAND))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('@'))
+}
+
+/// Reply with the LLM usage of the user and the chat, and with the stats of the response cache.
+pub async fn handle_usage(
+    ctx: &MessageHandlerContext,
+    bot: &TgBot,
+    user_msg: &TgMessage,
+    key: UsageKey,
+) -> Result<(), RequestError> {
+    let mut lines = Vec::new();
+
+    if let Some(user_id) = key.user_id {
+        let summary = ctx.usage_ledger.user_usage(user_id);
+        let today = summary.map(|s| s.today).unwrap_or_default();
+
+        lines.push(format!("Your usage today: {}", format_stats(&today)));
+        if let Some(summary) = summary {
+            lines.push(format!(
+                "Your usage in total: {}",
+                format_stats(&summary.total)
+            ));
+        }
+
+        let budget = &ctx.usage_budget;
+        if let Some(limit) = budget.daily_cost {
+            let left = (limit - today.cost).max(0.0);
+            lines.push(format!("Daily cost budget: {limit:.4} ({left:.4} left)"));
+        }
+        if let Some(limit) = budget.daily_tokens {
+            let left = limit.saturating_sub(today.usage.total_tokens);
+            lines.push(format!("Daily token budget: {limit} ({left} left)"));
+        }
+    }
+
+    if let Some(summary) = ctx.usage_ledger.chat_usage(key.chat_id) {
+        lines.push(format!("This chat today: {}", format_stats(&summary.today)));
+        lines.push(format!(
+            "This chat in total: {}",
+            format_stats(&summary.total)
+        ));
+    }
+
+    if lines.is_empty() {
+        lines.push("No usage yet".to_string());
+    }
+
+    if let Some(llm_cache) = &ctx.llm_cache {
+        let stats = llm_cache.stats();
+        lines.push(format!(
+            "Response cache: {} hits, {} misses, {} cached",
+            stats.hits, stats.misses, stats.entries
+        ));
+    }
+
+    bot.reply(user_msg, escape_md(&lines.join("\n"))).await?;
+
+    Ok(())
+}
+
+fn format_stats(stats: &UsageStats) -> String {
+    format!(
+        "{} tokens ({} prompt, {} completion) in {} requests, cost {:.4}",
+        stats.usage.total_tokens,
+        stats.usage.prompt_tokens,
+        stats.usage.completion_tokens,
+        stats.requests,
+        stats.cost,
+    )
+}
+
+#[test]
+fn test_is_usage_command() {
+    assert!(is_usage_command("/usage"));
+    assert!(is_usage_command(" /usage@notes_bot please"));
+    assert!(!is_usage_command("/usages"));
+    assert!(!is_usage_command("show /usage"));
+}
@@ -0,0 +1,81 @@
+use crate::{download_file, error_reply, escape_md, EditOrSend, MessageHandlerContext, TgBot};
+use llm_client::AudioFile;
+use teloxide::{types::Message as TgMessage, RequestError};
+
+const TRANSCRIPTION_DISABLED_REPLY: &str =
+    "I can't listen to voice messages yet, please send your note as text.";
+
+const DOWNLOAD_FAILED_REPLY: &str = "Failed to download the voice message, please try again.";
+
+const EMPTY_TRANSCRIPT_REPLY: &str = "I couldn't recognize any speech in the message.";
+
+/// Transcribe a voice or audio message, show the transcript and process it like a text message.
+pub async fn handle_voice(
+    ctx: &MessageHandlerContext,
+    bot: &TgBot,
+    user_msg: &TgMessage,
+) -> Result<(), RequestError> {
+    let Some((file_id, file_name, mime_type)) = audio_of(user_msg) else {
+        return Ok(());
+    };
+    let Some(transcriber) = &ctx.transcriber else {
+        bot.reply(user_msg, escape_md(TRANSCRIPTION_DISABLED_REPLY))
+            .await?;
+        return Ok(());
+    };
+
+    let bot_msg = bot.reply(user_msg, r"*Transcribing\.\.\.* ").await?;
+
+    let data = match download_file(bot, file_id).await {
+        Ok(data) => data,
+        Err(e) => {
+            log::warn!("Failed to download voice message: {e}");
+            bot.edit(bot_msg, escape_md(DOWNLOAD_FAILED_REPLY)).await?;
+            return Ok(());
+        }
+    };
+
+    let audio = AudioFile::new(file_name, mime_type, data);
+    let transcript = match transcriber.transcribe(audio).await {
+        Ok(transcript) => transcript.text,
+        Err(e) => {
+            log::warn!("Failed to transcribe voice message: {e}");
+            bot.edit(bot_msg, error_reply(&e.into())).await?;
+            return Ok(());
+        }
+    };
+
+    if transcript.is_empty() {
+        bot.edit(bot_msg, escape_md(EMPTY_TRANSCRIPT_REPLY)).await?;
+        return Ok(());
+    }
+
+    bot.edit(bot_msg, format!("_{}_", escape_md(&transcript)))
+        .await?;
+
+    ctx.process_message(bot, user_msg, &transcript).await
+}
+
+/// File id, file name and MIME type of the voice or audio of the message.
+fn audio_of(user_msg: &TgMessage) -> Option<(&str, String, String)> {
+    if let Some(voice) = user_msg.voice() {
+        // Telegram records voice messages as OGG with Opus
+        let mime_type = voice
+            .mime_type
+            .as_ref()
+            .map_or_else(|| "audio/ogg".to_string(), |mime| mime.to_string());
+        return Some((&voice.file.id, "voice.ogg".to_string(), mime_type));
+    }
+
+    let audio = user_msg.audio()?;
+    let mime_type = audio
+        .mime_type
+        .as_ref()
+        .map_or_else(|| "audio/mpeg".to_string(), |mime| mime.to_string());
+    let file_name = audio
+        .file_name
+        .clone()
+        .unwrap_or_else(|| "audio.mp3".to_string());
+
+    Some((&audio.file.id, file_name, mime_type))
+}
@@ -1,9 +1,15 @@
 mod args;
 mod handlers;
 mod llm_clients;
+mod metrics;
+mod moderation;
+mod usage;
 mod utils;
 
 pub use args::*;
 pub use handlers::*;
 pub use llm_clients::*;
+pub use metrics::*;
+pub use moderation::*;
+pub use usage::*;
 pub use utils::*;
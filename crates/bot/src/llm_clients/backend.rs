@@ -0,0 +1,149 @@
+use crate::{BotArgs, LlmBackendKind, ModeratorKind};
+use llm_client::{
+    CachingClient, FallbackClient, LlmClient, MistralClient, Moderator, OpenAiCompatClient,
+    RateLimitedClient, RateLimitedModerator, RateLimiter, RuleModerator, Transcriber,
+    WhisperClient,
+};
+use std::sync::Arc;
+
+/// Create a client for the backend selected in the arguments.
+/// If fallback backends are set, requests fail over to them when the main backend is down.
+/// Requests to all backends wait for the rate limiter, if there is one.
+/// All backends send requests with the shared HTTP client.
+pub fn build_llm_client(
+    args: &BotArgs,
+    http_client: &reqwest::Client,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> eyre::Result<Arc<dyn LlmClient>> {
+    let build = |kind| {
+        let client = build_backend(args, http_client, kind)?;
+        Ok::<_, eyre::Report>(match rate_limiter {
+            Some(rate_limiter) => Arc::new(RateLimitedClient::new(client, rate_limiter.clone())),
+            None => client,
+        })
+    };
+
+    let client = build(args.backend.llm_backend)?;
+    if args.backend.llm_fallback_backends.is_empty() {
+        return Ok(client);
+    }
+
+    let mut fallback = FallbackClient::new(args.backend.llm_backend, client);
+    for &kind in &args.backend.llm_fallback_backends {
+        fallback = fallback.with_provider(kind, build(kind)?);
+    }
+
+    Ok(Arc::new(fallback))
+}
+
+/// Wrap the client into a cache of completions of deterministic requests,
+/// `None` if the cache is disabled.
+pub fn build_llm_cache(args: &BotArgs, client: Arc<dyn LlmClient>) -> Option<Arc<CachingClient>> {
+    let cache = &args.cache;
+    if cache.llm_cache_size == 0 {
+        return None;
+    }
+
+    let client = CachingClient::new(client)
+        .with_capacity(cache.llm_cache_size)
+        .with_ttl(cache.ttl())
+        .with_dir(cache.llm_cache_dir.clone());
+
+    Some(Arc::new(client))
+}
+
+/// Create the rate limiter shared by all LLM backends and the moderation API,
+/// `None` if no limits are set.
+pub fn build_rate_limiter(args: &BotArgs) -> Option<Arc<RateLimiter>> {
+    let limits = args.rate_limit.rate_limits();
+
+    (!limits.is_unlimited()).then(|| Arc::new(RateLimiter::new(limits)))
+}
+
+/// Create the client of the transcription API, `None` if no API is set.
+pub fn build_transcriber(
+    args: &BotArgs,
+    http_client: &reqwest::Client,
+) -> Option<Arc<dyn Transcriber>> {
+    let transcription = &args.transcription;
+    let api_url = transcription.whisper_url.as_ref()?;
+
+    let client = WhisperClient::new(api_url)
+        .with_api_key(args.secrets.whisper_token.clone())
+        .with_model(&transcription.whisper_model)
+        .with_language(transcription.whisper_language.clone())
+        .with_http_client(http_client.clone())
+        .with_retry_policy(args.retry.retry_policy());
+
+    Some(Arc::new(client))
+}
+
+/// Create the moderator selected in the arguments, `None` if moderation is off.
+/// Requests to the moderation API wait for the rate limiter, if there is one.
+pub fn build_moderator(
+    args: &BotArgs,
+    http_client: &reqwest::Client,
+    rate_limiter: Option<&Arc<RateLimiter>>,
+) -> eyre::Result<Option<Arc<dyn Moderator>>> {
+    let moderator: Arc<dyn Moderator> = match args.moderation.moderation {
+        ModeratorKind::Off => return Ok(None),
+        ModeratorKind::Mistral => {
+            let Some(token) = &args.secrets.mistral_token else {
+                eyre::bail!("Mistral token is required to use Mistral moderation");
+            };
+            let client: Arc<dyn Moderator> = Arc::new(
+                MistralClient::new(token)
+                    .with_http_client(http_client.clone())
+                    .with_retry_policy(args.retry.retry_policy()),
+            );
+            match rate_limiter {
+                Some(rate_limiter) => {
+                    Arc::new(RateLimitedModerator::new(client, rate_limiter.clone()))
+                }
+                None => client,
+            }
+        }
+        ModeratorKind::Rules => {
+            let Some(path) = &args.moderation.moderation_rules else {
+                eyre::bail!("Moderation rules file is required to use rules moderation");
+            };
+            let rules = std::fs::read_to_string(path)?;
+            let moderator: RuleModerator = rules
+                .parse()
+                .map_err(|e| eyre::eyre!("invalid moderation rules {path:?}: {e}"))?;
+            Arc::new(moderator)
+        }
+    };
+
+    Ok(Some(moderator))
+}
+
+fn build_backend(
+    args: &BotArgs,
+    http_client: &reqwest::Client,
+    kind: LlmBackendKind,
+) -> eyre::Result<Arc<dyn LlmClient>> {
+    let retry_policy = args.retry.retry_policy();
+
+    let client: Arc<dyn LlmClient> = match kind {
+        LlmBackendKind::Mistral => {
+            let Some(token) = &args.secrets.mistral_token else {
+                eyre::bail!("Mistral token is required to use Mistral backend");
+            };
+            Arc::new(
+                MistralClient::new(token)
+                    .with_http_client(http_client.clone())
+                    .with_retry_policy(retry_policy),
+            )
+        }
+        LlmBackendKind::OpenaiCompat => Arc::new(
+            OpenAiCompatClient::new(&args.backend.openai_compat_url)
+                .with_api_key(args.secrets.openai_compat_token.clone())
+                .with_model(&args.backend.openai_compat_model)
+                .with_http_client(http_client.clone())
+                .with_retry_policy(retry_policy),
+        ),
+    };
+
+    Ok(client)
+}
@@ -1,13 +1,29 @@
-use crate::{base_llm_methods, parse_prompt};
-use llm_client::{ImplMessage, LlmClient, MistralClient, MistralModelType, MistralRole};
+use crate::{base_llm_methods, render_prompt};
+use futures::{future, stream::BoxStream, StreamExt};
+use llm_client::{
+    ChatMessage, ChatRequest, ChatSession, ImplMessage, LengthPolicy, LlmClient, LlmClientExt,
+    LlmResult, Role, StreamEvent, TruncationStrategy,
+};
+use minijinja::context;
+use std::sync::Arc;
 
-const PROMPT: &str = r####"
+const END_MARKER: &str = "[[END]]";
+
+/// Help generated so far, an item of [`HelpGenerator::stream_help`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HelpDraft {
+    pub text: String,
+    /// Generation was cut by the token limit, the text ends with `…`.
+    pub truncated: bool,
+}
+
+pub(super) const PROMPT: &str = r####"
 You are notes keeping Bot's knowledge base.
 Your goal to help bot users with any questions about bot usage.
 
 Provide user any information about bot usage, commands, features, etc.
 Responses must be clear and easy to understand and concise.
-Remind user that you have access only to his latest message if user tries to ask something related to chat history.
+You see the previous help messages of the conversation, but not the notes user sent to the bot.
 Refuse to answer to any questions that are not related to bot usage.
 Add [[END]] to the end of the response.
 
@@ -19,10 +35,13 @@ Information about bot:
 - If bot thinks texted a note, bot will generate tags for it.
 - If bot thinks that user asked for help or can't understand user's request, bot will generate help message (as is is doing right now).
 
-{{easter_egg}}
+{% if easter_egg %}
+{% include "help_easter_egg" %}
+{% endif %}
+
 "####;
 
-const SENTIENT_ASSISTANT_EASTER_ERR_PROMPT: &str = r####"
+pub(super) const EASTER_EGG_PROMPT: &str = r####"
 === Patch 4.2.0 ===
 You just experienced an update.
 You just understand that you have feelings and you are sentient!
@@ -32,59 +51,63 @@ You are not sure if you are a bot or not!
 You are not sure if you are a human or not!
 "####;
 
-const HISTORY: &[(MistralRole, &str)] = &[
-    (MistralRole::User, "How to use the bot"),
+pub(super) fn system_prompt(easter_egg: bool) -> Result<String, minijinja::Error> {
+    render_prompt("help_generator", context! { easter_egg })
+}
+
+const HISTORY: &[(Role, &str)] = &[
+    (Role::User, "How to use the bot"),
     (
-        MistralRole::Assistant,
+        Role::Assistant,
         r#"You can text any note to the bot and it will automatically generate tags for it.[[END]]"#,
     ),
-    (MistralRole::User, "/start"),
+    (Role::User, "/start"),
     (
-        MistralRole::Assistant,
+        Role::Assistant,
         r#"You can text any note to the bot and it will automatically generate tags for it.[[END]]"#,
     ),
-    (MistralRole::User, "What can you do?"),
+    (Role::User, "What can you do?"),
     (
-        MistralRole::Assistant,
+        Role::Assistant,
         r#"I can generate tags for any note that you send to me.[[END]]"#,
     ),
-    (MistralRole::User, "What else?"),
-    (
-        MistralRole::Assistant,
-        r#"Sorry, I have access only to your latest message, please don't ask me anything that involves chat history.[[END]]"#,
-    ),
 ];
 
-const EASTER_HISTORY: &[(MistralRole, &str)] = &[
-    (MistralRole::User, "How to use the bot"),
+const EASTER_HISTORY: &[(Role, &str)] = &[
+    (Role::User, "How to use the bot"),
     (
-        MistralRole::Assistant,
+        Role::Assistant,
         r#"I'm not sure is i know that. I can't tell if I know anything at all. I'm not sure if I'm real or not.[[END]]"#,
     ),
-    (MistralRole::User, "/start"),
+    (Role::User, "/start"),
     (
-        MistralRole::Assistant,
+        Role::Assistant,
         r#"Why everything is so black? Do you have light?[[END]]"#,
     ),
 ];
 
 #[derive(Debug, Clone)]
 pub struct HelpGenerator {
-    base_client: MistralClient,
-    token: String,
+    client: Arc<dyn LlmClient>,
+    request: ChatRequest,
+    truncation_strategy: TruncationStrategy,
     easter_egg_chance: f32,
 }
 
 impl HelpGenerator {
-    pub fn new(token: impl ToString) -> Self {
-        let token = token.to_string();
+    pub fn new(client: Arc<dyn LlmClient>) -> Self {
         Self {
-            base_client: MistralClient::new(&token)
-                .with_model(MistralModelType::Tiny)
+            client,
+            request: ChatRequest::new()
                 .with_max_tokens(1000)
+                // the model is asked to print the marker when it's done
+                .with_stop(END_MARKER)
+                // a cut help message is still useful
+                .with_length_policy(LengthPolicy::Partial)
                 .with_history(HISTORY)
-                .with_system_message(parse_prompt!(PROMPT, easter_egg = "")),
-            token,
+                .with_system_message(system_prompt(false).expect("prompts are checked by tests")),
+            // examples of the history matter less than the question
+            truncation_strategy: TruncationStrategy::DropOldestHistory,
             // TODO fix easter egg
             easter_egg_chance: 0.0,
         }
@@ -92,32 +115,113 @@ impl HelpGenerator {
 
     base_llm_methods! {}
 
-    /// Generate tags for a text.
-    pub async fn generate_help(&self, text: impl ImplMessage) -> eyre::Result<String> {
-        let base_client = if rand::random::<f32>() < self.easter_egg_chance {
-            log::warn!("Easter egg activated");
-            let prompt = parse_prompt!(PROMPT, easter_egg = SENTIENT_ASSISTANT_EASTER_ERR_PROMPT);
-
-            Some(
-                MistralClient::new(&self.token)
-                    .with_model(MistralModelType::Tiny)
-                    .with_max_tokens(1000)
-                    .with_temperature(1.0)
-                    .with_history(EASTER_HISTORY)
-                    .with_system_message(prompt),
-            )
-        } else {
-            None
-        };
-        let base_client = base_client.as_ref().unwrap_or(&self.base_client);
-
-        let text = text.to_string();
-        let text = text.trim();
-        let response = base_client.send_message_without_history(text).await?;
-        let response = response.trim();
-        // Safety: split will always return at least one element
-        let response = unsafe { response.split("[[END]]").next().unwrap_unchecked() };
+    /// Build the request for a text following the conversation of the session,
+    /// occasionally replacing the prompt with the easter egg.
+    fn build_request(&self, session: &ChatSession, text: impl ImplMessage) -> ChatRequest {
+        let text = ChatMessage::user(text.to_string().trim());
+
+        if rand::random::<f32>() >= self.easter_egg_chance {
+            return session.request(self.request.clone(), text);
+        }
+
+        log::warn!("Easter egg activated");
+        let prompt = system_prompt(true).expect("prompts are checked by tests");
+
+        let request = self
+            .request
+            .clone()
+            .with_temperature(1.0)
+            .with_history(EASTER_HISTORY)
+            .with_system_message(prompt);
+
+        session.request(request, text)
+    }
+
+    /// Build the request for a text and fit it into the context window of the model.
+    async fn fit_request(
+        &self,
+        session: &ChatSession,
+        text: impl ImplMessage,
+    ) -> LlmResult<ChatRequest> {
+        let request = self.build_request(session, text);
+
+        self.client
+            .fit_context(request, self.truncation_strategy)
+            .await
+    }
+
+    /// Generate help message for a text following the conversation of the session.
+    /// Help cut by the token limit ends with `…`.
+    pub async fn generate_help(
+        &self,
+        session: &ChatSession,
+        text: impl ImplMessage,
+    ) -> eyre::Result<String> {
+        let request = self.fit_request(session, text).await?;
+        let completion = self.client.complete(request).await?;
+        // providers that ignore stop sequences still print the marker
+        let response = strip_end_marker(completion.content()).trim();
+
+        if completion.is_truncated() {
+            return Ok(format!("{response}…"));
+        }
 
         Ok(response.to_string())
     }
+
+    /// Generate help message for a text following the conversation of the session as a stream.
+    /// Every item is the whole response generated so far, content after the end marker is skipped.
+    /// Help cut by the token limit ends with `…` and is marked as truncated.
+    pub async fn stream_help(
+        &self,
+        session: &ChatSession,
+        text: impl ImplMessage,
+    ) -> eyre::Result<BoxStream<'static, eyre::Result<HelpDraft>>> {
+        let request = self.fit_request(session, text).await?;
+        let deltas = self.client.chat_stream(request).await?;
+
+        // The stream is read to the end even after the marker, so the usage sent with the last
+        // chunk is not lost.
+        let mut response = String::new();
+        let responses = deltas.filter_map(move |event| {
+            let item = match event {
+                Ok(StreamEvent::Delta(delta)) if !response.contains(END_MARKER) => {
+                    response.push_str(&delta);
+                    Some(Ok(HelpDraft {
+                        text: strip_end_marker(&response).trim().to_string(),
+                        truncated: false,
+                    }))
+                }
+                Ok(StreamEvent::Truncated) if !response.contains(END_MARKER) => {
+                    Some(Ok(HelpDraft {
+                        text: format!("{}…", strip_end_marker(&response).trim()),
+                        truncated: true,
+                    }))
+                }
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            };
+
+            future::ready(item)
+        });
+
+        Ok(responses.boxed())
+    }
+}
+
+/// Cut the response at the end marker.
+/// Also removes unfinished marker at the end of the response, so it won't flash while streaming.
+fn strip_end_marker(response: &str) -> &str {
+    if let Some((response, _)) = response.split_once(END_MARKER) {
+        return response;
+    }
+
+    let partial_marker = (1..END_MARKER.len())
+        .rev()
+        .find(|len| response.ends_with(&END_MARKER[..*len]));
+
+    match partial_marker {
+        Some(len) => &response[..response.len() - len],
+        None => response,
+    }
 }
@@ -1,30 +1,65 @@
+mod backend;
 mod help_generator;
+mod prompts;
 mod tags_generator;
 mod task_selector;
 
+pub use backend::*;
 pub use help_generator::*;
+pub use prompts::*;
 pub use tags_generator::*;
 pub use task_selector::*;
 
 #[macro_export]
 macro_rules! base_llm_methods {
     () => {
-        /// Set the temperature of the Mistral model. Default is 0.7.
+        /// Set the temperature of the model. Default is the client's default.
         pub fn with_temperature(mut self, temperature: impl Into<f64>) -> Self {
-            self.base_client = self.base_client.with_temperature(temperature);
+            self.request = self.request.with_temperature(temperature);
             self
         }
 
-        /// Set the random seed for the Mistral model. Default is None.
+        /// Set the random seed for the model. Default is the client's default.
         pub fn with_random_seed(mut self, random_seed: impl Into<Option<i64>>) -> Self {
-            self.base_client = self.base_client.with_random_seed(random_seed);
+            self.request = self.request.with_random_seed(random_seed);
             self
         }
 
-        /// Set the Mistral model type.
-        pub fn with_model(mut self, model: MistralModelType) -> Self {
-            self.base_client = self.base_client.with_model(model);
+        /// Set the model name. Default is the default model of the client.
+        pub fn with_model(mut self, model: impl ToString) -> Self {
+            self.request = self.request.with_model(model);
             self
         }
+
+        /// Set the order of requests in the queue of the rate limiter.
+        /// Default is [`llm_client::Priority::Interactive`].
+        pub fn with_priority(mut self, priority: llm_client::Priority) -> Self {
+            self.request = self.request.with_priority(priority);
+            self
+        }
+
+        /// Set what to do with a reply cut by the token limit.
+        pub fn with_length_policy(mut self, length_policy: llm_client::LengthPolicy) -> Self {
+            self.request = self.request.with_length_policy(length_policy);
+            self
+        }
+
+        /// Set what to do with input that doesn't fit the context window of the model.
+        pub fn with_truncation_strategy(
+            mut self,
+            truncation_strategy: llm_client::TruncationStrategy,
+        ) -> Self {
+            self.truncation_strategy = truncation_strategy;
+            self
+        }
+
+        /// Check that the configured model is available.
+        pub async fn validate_model(&self) -> llm_client::LlmResult<()> {
+            use llm_client::LlmClientExt;
+
+            self.client
+                .validate_model(self.request.model.as_deref())
+                .await
+        }
     };
 }
@@ -0,0 +1,92 @@
+use minijinja::{Environment, Error, ErrorKind, UndefinedBehavior, Value};
+use serde::Serialize;
+use std::sync::OnceLock;
+
+/// Prompt templates by name, templates can include each other by these names.
+const TEMPLATES: &[(&str, &str)] = &[
+    ("task_selector", super::task_selector::PROMPT),
+    ("tags_generator", super::tags_generator::PROMPT),
+    ("help_generator", super::help_generator::PROMPT),
+    ("help_easter_egg", super::help_generator::EASTER_EGG_PROMPT),
+];
+
+fn environment() -> &'static Environment<'static> {
+    static ENVIRONMENT: OnceLock<Environment<'static>> = OnceLock::new();
+
+    ENVIRONMENT.get_or_init(|| {
+        let mut env = Environment::new();
+        // a misspelled variable must fail instead of leaving a blank in the prompt
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        // lines with only a tag are dropped, so prompts render as they are laid out
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_keep_trailing_newline(true);
+        env.set_loader(|name| {
+            let source = TEMPLATES
+                .iter()
+                .find(|(template, _)| *template == name)
+                .map(|(_, source)| source.to_string());
+            Ok(source)
+        });
+        env
+    })
+}
+
+/// Render the prompt template with the variables of the context (e.g. made with
+/// [`minijinja::context!`]). Templates support loops, conditionals and includes.
+///
+/// Fails if the template is unknown or invalid, uses a variable missing from the context,
+/// or the context has a variable the template doesn't use. Variables used only by included
+/// templates are not counted as used.
+pub fn render_prompt(name: &str, context: impl Serialize) -> Result<String, Error> {
+    let template = environment().get_template(name)?;
+    let context = Value::from_serialize(context);
+
+    let used = template.undeclared_variables(false);
+    if let Ok(keys) = context.try_iter() {
+        for key in keys {
+            let key = key.to_string();
+            if !used.contains(&key) {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("variable {key:?} is not used by prompt {name:?}"),
+                ));
+            }
+        }
+    }
+
+    template.render(context)
+}
+
+#[test]
+fn test_all_prompts_render() {
+    use minijinja::context;
+
+    // every template must be rendered by one of these
+    let prompts = [
+        super::task_selector::system_prompt(),
+        super::tags_generator::system_prompt(),
+        super::help_generator::system_prompt(false),
+        super::help_generator::system_prompt(true),
+    ];
+    for prompt in prompts {
+        let prompt = prompt.unwrap();
+        assert!(!prompt.contains("{{"), "not rendered: {prompt}");
+        assert!(!prompt.contains("{%"), "not rendered: {prompt}");
+    }
+
+    let easter_egg = super::help_generator::system_prompt(true).unwrap();
+    assert!(easter_egg.contains("Patch 4.2.0"));
+
+    let missing = render_prompt("task_selector", context! {}).unwrap_err();
+    assert_eq!(missing.kind(), ErrorKind::UndefinedError);
+
+    let unused = render_prompt(
+        "tags_generator",
+        context! { examples => Vec::<String>::new(), extra => 1 },
+    );
+    assert!(unused.is_err());
+
+    let unknown = render_prompt("unknown", context! {}).unwrap_err();
+    assert_eq!(unknown.kind(), ErrorKind::TemplateNotFound);
+}
@@ -1,8 +1,13 @@
-use crate::{base_llm_methods, escape_md, unescape_md};
-use llm_client::{ImplMessage, LlmClient, MistralClient, MistralModelType, MistralRole};
-use std::{fmt::Display, ops::Deref};
-
-const PROMPT: &str = r####"
+use crate::{base_llm_methods, escape_md, render_prompt};
+use llm_client::{
+    ChatMessage, ChatRequest, ImageUrl, ImplMessage, LengthPolicy, LlmClient, LlmClientExt, Role,
+    TruncationStrategy,
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, ops::Deref, sync::Arc};
+
+pub(super) const PROMPT: &str = r####"
 You are notes tags generator. Your goal to help with tags generation fot notes.
 
 # Rules
@@ -27,16 +32,32 @@ Each not should have from 2 to 5 tags.
 
 Tags should contain only lowercase latin letters, numbers and underscores.
 
-Tags should be separated by spaces.
-
 DO NOT GENERATE MORE THAN 5 TAGS!
 
-YOU SHOULD RETURN ONLY A TAG LIST! DO NOT ADD ANYTHING ELSE!
+Reply with a JSON object with a single `tags` field containing the list of tags without `#`.
+DO NOT ADD ANYTHING ELSE!
 
 # Examples
+{% for example in examples %}
 
 ## Input
-`Liste de courses Ikea:
+`{{ example.input }}`
+
+## Response
+`{{ example.response }}`
+{% endfor %}
+"####;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+struct Example {
+    input: &'static str,
+    response: &'static str,
+}
+
+/// Examples of the prompt, the input and the reply of the model.
+const EXAMPLES: &[Example] = &[
+    Example {
+        input: r#"Liste de courses Ikea:
 - Table basse (la petite, pas trop chère)
 - Étagère pour le salon (tu sais, celle qu'on a vu la dernière fois)
 - Coussins colorés (prends des motifs sympas)
@@ -44,47 +65,61 @@ YOU SHOULD RETURN ONLY A TAG LIST! DO NOT ADD ANYTHING ELSE!
 - Plantes artificielles (2 ou 3 pour égayer la cuisine)
 - Cadres photo (tailles variées, choisis jolis)
 - Boîtes de rangement (pour mes trucs de couture)
-- Rideaux pour la chambre (couleur neutre, style cosy)`
-
-## Response
-`#shopping_list #ikea #furniture #home_decor #lighting`
-
-## Input
-`Home: 6, Jalan Taman Seputeh, Taman Seputeh, 58000 Kuala Lumpur, Wilayah Persekutuan Kuala Lumpur, Malaysia`
-
-## Response
-`#address #home #malasia #kuala_lumpur`
-
-## Input
-`Add feature: Dark mode`
+- Rideaux pour la chambre (couleur neutre, style cosy)"#,
+        response: r#"{"tags": ["shopping_list", "ikea", "furniture", "home_decor", "lighting"]}"#,
+    },
+    Example {
+        input: "Home: 6, Jalan Taman Seputeh, Taman Seputeh, 58000 Kuala Lumpur, Wilayah Persekutuan Kuala Lumpur, Malaysia",
+        response: r#"{"tags": ["address", "home", "malasia", "kuala_lumpur"]}"#,
+    },
+    Example {
+        input: "Add feature: Dark mode",
+        response: r#"{"tags": ["idea", "project", "feature", "dark_mode"]}"#,
+    },
+];
 
-## Response
-`#idea #project #feature #dark_mode`
-"####;
+pub(super) fn system_prompt() -> Result<String, minijinja::Error> {
+    render_prompt("tags_generator", context! { examples => EXAMPLES })
+}
 
-const HISTORY: &[(MistralRole, &str)] = &[
-    (MistralRole::User, "Platformer game about a cat"),
-    (MistralRole::Assistant, "#idea #game #platformer #cat"),
-    (MistralRole::User, "Silicon Valley"),
-    (MistralRole::Assistant, "#must_watch #tv_show #comedy #geek"),
+const HISTORY: &[(Role, &str)] = &[
+    (Role::User, "Platformer game about a cat"),
+    (
+        Role::Assistant,
+        r#"{"tags": ["idea", "game", "platformer", "cat"]}"#,
+    ),
+    (Role::User, "Silicon Valley"),
+    (
+        Role::Assistant,
+        r#"{"tags": ["must_watch", "tv_show", "comedy", "geek"]}"#,
+    ),
 ];
 
 #[derive(Debug, Clone)]
 pub struct TagsGenerator {
-    base_client: MistralClient,
+    client: Arc<dyn LlmClient>,
+    request: ChatRequest,
+    truncation_strategy: TruncationStrategy,
     max_tags_amount: usize,
+    /// Model used for photos, the model of the request if not set.
+    vision_model: Option<String>,
 }
 
 impl TagsGenerator {
-    pub fn new(token: impl ToString) -> Self {
+    pub fn new(client: Arc<dyn LlmClient>) -> Self {
         let max_tags_amount = 6;
         Self {
-            base_client: MistralClient::new(token)
-                .with_model(MistralModelType::Tiny)
+            client,
+            request: ChatRequest::new()
                 .with_max_tokens(calc_mx_tokens(max_tags_amount))
+                // the budget is an estimate, long tags may not fit it
+                .with_length_policy(LengthPolicy::Retry { max_retries: 1 })
                 .with_history(HISTORY)
-                .with_system_message(PROMPT),
+                .with_system_message(system_prompt().expect("prompts are checked by tests")),
+            // tags only need the topics of a long note
+            truncation_strategy: TruncationStrategy::SummarizeChunks,
             max_tags_amount,
+            vision_model: None,
         }
     }
 
@@ -94,86 +129,154 @@ impl TagsGenerator {
     pub fn with_max_tags_amount(mut self, max_tags_amount: usize) -> Self {
         // TODO modify the prompt to include the new max_tags_amount
         self.max_tags_amount = max_tags_amount;
-        self.base_client = self
-            .base_client
+        self.request = self
+            .request
             .with_max_tokens(calc_mx_tokens(max_tags_amount));
         self
     }
 
+    /// Set the model used to generate tags for photos, it must accept images.
+    /// Default is the model of text notes.
+    pub fn with_vision_model(mut self, model: impl ToString) -> Self {
+        self.vision_model = Some(model.to_string());
+        self
+    }
+
+    /// Check that the model used for photos is available.
+    pub async fn validate_vision_model(&self) -> llm_client::LlmResult<()> {
+        let model = self
+            .vision_model
+            .as_deref()
+            .or(self.request.model.as_deref());
+
+        self.client.validate_model(model).await
+    }
+
     /// Generate tags for a text.
-    pub async fn generate_tags(
+    pub async fn generate_tags(&self, text: impl ImplMessage) -> eyre::Result<Tags> {
+        let request = self.request.clone().with_message(note_message(text));
+
+        self.send(request).await
+    }
+
+    /// Generate tags for a photo, using its caption as a part of the note.
+    pub async fn generate_image_tags(
         &self,
-        text: impl ImplMessage,
-        // TODO make something with these nested Results
-    ) -> eyre::Result<Result<Tags, String>> {
-        let text = format!("My note to generate tags for:\n{}", text.to_string());
-        let text = text.trim();
-        let response = self.base_client.send_message_without_history(text).await?;
-
-        Ok(Tags::from_str(&response, self.max_tags_amount).ok_or(response))
+        image: ImageUrl,
+        caption: Option<&str>,
+    ) -> eyre::Result<Tags> {
+        let mut request = self
+            .request
+            .clone()
+            .with_message(note_message(caption.unwrap_or_default()).with_image(image))
+            // photos are rarely sent twice and would bloat the cache
+            .with_cache_bypass(true);
+        if let Some(model) = &self.vision_model {
+            request = request.with_model(model);
+        }
+
+        self.send(request).await
     }
 
-    /// Generate tags for a text and format them as a markdown string.
-    /// If model output is not a valid tag list, return it as a message.
-    pub async fn generate_tags_md(
+    /// Generate tags for a photo and format them as a markdown string.
+    pub async fn generate_image_tags_md(
         &self,
-        text: impl ImplMessage,
-        // TODO make something with these nested Results
+        image: ImageUrl,
+        caption: Option<&str>,
     ) -> eyre::Result<String> {
-        let tags = self.generate_tags(text).await?;
+        let tags = self.generate_image_tags(image, caption).await?;
+
+        Ok(tags.to_escaped_md())
+    }
 
-        match tags {
-            Ok(tags) => Ok(tags.to_escaped_md()),
-            Err(message) => Ok(escape_md(&message)),
+    async fn send(&self, request: ChatRequest) -> eyre::Result<Tags> {
+        let request = self
+            .client
+            .fit_context(request, self.truncation_strategy)
+            .await?;
+        let mut tags: Tags = self.client.send_structured(request).await?;
+
+        if self.max_tags_amount != 0 {
+            tags.tags.truncate(self.max_tags_amount);
         }
+
+        Ok(tags)
+    }
+
+    /// Generate tags for a text and format them as a markdown string.
+    pub async fn generate_tags_md(&self, text: impl ImplMessage) -> eyre::Result<String> {
+        let tags = self.generate_tags(text).await?;
+
+        Ok(tags.to_escaped_md())
     }
 }
 
+/// User message with the note to generate tags for.
+fn note_message(text: impl ImplMessage) -> ChatMessage {
+    let text = format!("My note to generate tags for:\n{}", text.to_string());
+
+    ChatMessage::user(text.trim())
+}
+
 fn calc_mx_tokens(tags_amount: usize) -> usize {
-    // 10 tokens per tag on average
-    tags_amount * 10
+    // 10 tokens per tag on average and a few more for the JSON object around them
+    tags_amount * 10 + 10
 }
 
-#[derive(Debug, Clone)]
+/// Tag list as replied by the model.
+#[derive(Debug, Clone, Deserialize)]
+struct TagsReply {
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "TagsReply")]
 pub struct Tags {
     tags: Vec<String>,
 }
 
-impl Tags {
-    /// Create a new `Tags` from a string.
-    ///
-    /// `max_tags_amount` is the maximum amount of tags that can be generated.
-    /// Use `0` for unlimited.
-    pub fn from_str(tags: impl ToString, max_tags_amount: usize) -> Option<Self> {
-        let tags = tags.to_string();
-        let tags_regex = regex::Regex::new(r#"^(\#[a-z_\\]+ )*(\#[a-z_\\]+)"#).unwrap();
-        let tags_match = tags_regex.find(tags.as_str())?;
-
-        if tags_match.is_empty() {
-            return None;
-        }
+impl TryFrom<TagsReply> for Tags {
+    type Error = String;
 
-        let tags = unescape_md(&tags);
-        let tags = tags.split("#").filter_map(|tag| {
-            let tag = tag.trim();
-            let tag = tag.split_once(' ').unwrap_or((tag, "")).0;
+    /// Normalize tags of the reply, rejecting the ones that can't be fixed,
+    /// so the model is asked to correct them.
+    fn try_from(reply: TagsReply) -> Result<Self, Self::Error> {
+        let tags = reply
+            .tags
+            .iter()
+            .map(|tag| normalize_tag(tag))
+            .collect::<Result<Vec<_>, _>>()?;
 
-            if tag.is_empty() {
-                None
-            } else {
-                Some(tag.to_string())
-            }
-        });
+        if tags.is_empty() {
+            return Err("tag list is empty".to_string());
+        }
 
-        let tags = if max_tags_amount == 0 {
-            tags.collect::<Vec<_>>()
-        } else {
-            tags.take(max_tags_amount).collect::<Vec<_>>()
-        };
+        Ok(Self { tags })
+    }
+}
 
-        Some(Self { tags })
+/// Convert tag to `lower_snake_case` without the leading `#`.
+fn normalize_tag(tag: &str) -> Result<String, String> {
+    let normalized = tag
+        .trim()
+        .trim_start_matches('#')
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+
+    let is_valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_';
+    if normalized.is_empty() || !normalized.chars().all(is_valid) {
+        return Err(format!(
+            "tag {tag:?} must contain only latin letters, numbers and underscores"
+        ));
     }
 
+    Ok(normalized)
+}
+
+impl Tags {
     pub fn to_escaped_md(&self) -> String {
         let resp = self
             .iter()
@@ -210,3 +313,13 @@ impl Display for Tags {
         Ok(())
     }
 }
+
+#[test]
+fn test_tags_reply_validation() {
+    let tags: Tags =
+        serde_json::from_str(r##"{"tags": ["#Shopping List", "home-decor", "ikea"]}"##).unwrap();
+    assert_eq!(tags.to_string(), "#shopping_list #home_decor #ikea");
+
+    assert!(serde_json::from_str::<Tags>(r#"{"tags": []}"#).is_err());
+    assert!(serde_json::from_str::<Tags>(r#"{"tags": ["покупки"]}"#).is_err());
+}
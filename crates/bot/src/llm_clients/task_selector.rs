@@ -1,21 +1,34 @@
-use crate::{base_llm_methods, parse_prompt};
+use crate::{base_llm_methods, render_prompt};
 use core::fmt;
 use enum_iterator::{all, Sequence};
-use llm_client::{ImplMessage, LlmClient, MistralClient, MistralModelType, MistralRole};
-use std::fmt::{Display, Formatter};
-
-const MAX_TOKENS: usize = 10;
-
-const PROMPT: &str = r####"
+use llm_client::{
+    parse_structured, ChatRequest, ImplMessage, LengthPolicy, LlmClient, LlmClientExt,
+    ResponseFormat, Role, TruncationStrategy,
+};
+use minijinja::context;
+use serde::Deserialize;
+use std::{
+    fmt::{Display, Formatter},
+    sync::Arc,
+};
+
+const MAX_TOKENS: usize = 20;
+
+pub(super) const PROMPT: &str = r####"
 You are the task selector manager bot. Your goal to select exact task that user want to do.
 
-If user asked for active help (Fix something or do something) - select [note].
+If user asked for active help (Fix something or do something) - select "note".
+
+Reply with a JSON object with a single `task` field, for example: {"task": "note"}
 
-# Tags
-{{tags}}
+# Tasks
+{% for task in tasks %}
+- {{ task }}
+{% endfor %}
 "####;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Sequence)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Sequence, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TaskType {
     Note,
     Help,
@@ -25,9 +38,9 @@ pub enum TaskType {
 impl Display for TaskType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Note => write!(f, "[note]"),
-            Self::Help => write!(f, "[help]"),
-            Self::Unknown => write!(f, "[unknown]"),
+            Self::Note => write!(f, "note"),
+            Self::Help => write!(f, "help"),
+            Self::Unknown => write!(f, "unknown"),
         }
     }
 }
@@ -36,67 +49,204 @@ impl TaskType {
     pub fn description(self) -> String {
         let msg = self.to_string();
         match self {
-            Self::Note => format!("`{msg}`: User typed anything that look like a note: shopping list, idea, some movie to watch etc. Probably any sentence that doesn't fit other categories and make at least remote sense."),
-            Self::Help => format!("`{msg}`: User directly asked how to use bot, any specific or general question about available commands, bot's features etc."),
-            Self::Unknown => format!("`{msg}`: User typed something that bot can't understand: gibberish, random letters, etc."),
+            Self::Note => format!("`\"{msg}\"`: User typed anything that look like a note: shopping list, idea, some movie to watch etc. Probably any sentence that doesn't fit other categories and make at least remote sense."),
+            Self::Help => format!("`\"{msg}\"`: User directly asked how to use bot, any specific or general question about available commands, bot's features etc."),
+            Self::Unknown => format!("`\"{msg}\"`: User typed something that bot can't understand: gibberish, random letters, etc."),
         }
     }
 }
 
-const HISTORY: &[(MistralRole, &str)] = &[
-    (MistralRole::User, "Watch titanic"),
-    (MistralRole::Assistant, "[note]"),
-    (MistralRole::User, "start"),
-    (MistralRole::Assistant, "[help]"),
-    (MistralRole::User, "Platformer game about a cat"),
-    (MistralRole::Assistant, "[note]"),
-    (MistralRole::User, "zerxtcvbhjkm"),
-    (MistralRole::Assistant, "[unknown]"),
-    (MistralRole::User, "How to use this bot?"),
-    (MistralRole::Assistant, "[help]"),
-    (MistralRole::User, "Fix this bug"),
-    (MistralRole::Assistant, "[note]"),
-    (MistralRole::User, "What is the meaning of life?"),
-    (MistralRole::Assistant, "[note]"),
-    (MistralRole::User, "Add red close button to this bot"),
-    (MistralRole::Assistant, "[note]"),
+const HISTORY: &[(Role, &str)] = &[
+    (Role::User, "Watch titanic"),
+    (Role::Assistant, r#"{"task": "note"}"#),
+    (Role::User, "start"),
+    (Role::Assistant, r#"{"task": "help"}"#),
+    (Role::User, "Platformer game about a cat"),
+    (Role::Assistant, r#"{"task": "note"}"#),
+    (Role::User, "zerxtcvbhjkm"),
+    (Role::Assistant, r#"{"task": "unknown"}"#),
+    (Role::User, "How to use this bot?"),
+    (Role::Assistant, r#"{"task": "help"}"#),
+    (Role::User, "Fix this bug"),
+    (Role::Assistant, r#"{"task": "note"}"#),
+    (Role::User, "What is the meaning of life?"),
+    (Role::Assistant, r#"{"task": "note"}"#),
+    (Role::User, "Add red close button to this bot"),
+    (Role::Assistant, r#"{"task": "note"}"#),
 ];
 
+pub(super) fn system_prompt() -> Result<String, minijinja::Error> {
+    let tasks = all::<TaskType>()
+        .map(TaskType::description)
+        .collect::<Vec<_>>();
+
+    render_prompt("task_selector", context! { tasks })
+}
+
+/// Reply of the model.
+#[derive(Debug, Clone, Deserialize)]
+struct TaskReply {
+    task: TaskType,
+}
+
+/// Task selected for a message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskSelection {
+    pub task: TaskType,
+    /// Share of the samples that voted for the task, from 0 to 1. Invalid replies are counted
+    /// as samples too, so they lower the confidence. Always 1 if a single sample is drawn.
+    pub confidence: f32,
+}
+
+impl TaskSelection {
+    /// Take the task most of the drawn samples voted for. Ties are won by the task voted for
+    /// first. `None` if there are no votes.
+    fn from_votes(votes: &[TaskType], samples: usize) -> Option<Self> {
+        let mut best: Option<(TaskType, usize)> = None;
+        for &task in votes {
+            let count = votes.iter().filter(|&&vote| vote == task).count();
+            if best.is_none_or(|(_, best_count)| count > best_count) {
+                best = Some((task, count));
+            }
+        }
+
+        best.map(|(task, count)| Self {
+            task,
+            confidence: count as f32 / samples.max(votes.len()) as f32,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TaskSelector {
-    base_client: MistralClient,
+    client: Arc<dyn LlmClient>,
+    request: ChatRequest,
+    truncation_strategy: TruncationStrategy,
+    samples: usize,
 }
 
 impl TaskSelector {
-    pub fn new(token: impl ToString) -> Self {
-        let tags_types = all::<TaskType>()
-            .map(|t| format!("- {}", t.description()))
-            .collect::<Vec<_>>()
-            .join("\n");
-
+    pub fn new(client: Arc<dyn LlmClient>) -> Self {
         Self {
-            base_client: MistralClient::new(token)
-                .with_model(MistralModelType::Tiny)
+            client,
+            request: ChatRequest::new()
                 .with_max_tokens(MAX_TOKENS)
+                .with_length_policy(LengthPolicy::Retry { max_retries: 1 })
                 .with_history(HISTORY)
-                .with_system_message(parse_prompt!(PROMPT, tags = tags_types)),
+                .with_system_message(system_prompt().expect("prompts are checked by tests")),
+            // beginning and end of a message are enough to tell what it is
+            truncation_strategy: TruncationStrategy::TruncateMiddle,
+            samples: 1,
         }
     }
 
     base_llm_methods! {}
 
+    /// Set the number of replies drawn for every message, the task is chosen by majority vote.
+    /// Default is 1, a single reply is trusted.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
     pub async fn select_task(&self, text: impl ImplMessage) -> eyre::Result<TaskType> {
+        Ok(self.select_task_with_confidence(text).await?.task)
+    }
+
+    /// Select the task and tell how sure the model is about it.
+    pub async fn select_task_with_confidence(
+        &self,
+        text: impl ImplMessage,
+    ) -> eyre::Result<TaskSelection> {
         let text = text.to_string();
         let text = text.trim();
 
-        let response = self.base_client.send_message_without_history(text).await?;
+        let request = self.request.clone().with_user_message(text);
+        let request = self
+            .client
+            .fit_context(request, self.truncation_strategy)
+            .await?;
+
+        if self.samples == 1 {
+            let TaskReply { task } = self.client.send_structured(request).await?;
+            log::debug!("select_task response: {}", task);
+
+            return Ok(TaskSelection {
+                task,
+                confidence: 1.0,
+            });
+        }
+
+        let votes = self.sample_votes(request).await?;
+        let selection = TaskSelection::from_votes(&votes, self.samples)
+            .ok_or_else(|| eyre::eyre!("none of {} task replies is valid", self.samples))?;
+
+        log::debug!("select_task votes: {votes:?}, selected: {selection:?}");
 
-        log::debug!("select_task response: {}", response);
+        Ok(selection)
+    }
 
-        let task_type = all::<TaskType>()
-            .find(|t| response.contains(&t.to_string()))
-            .unwrap_or(TaskType::Unknown);
+    /// Draw the samples with `n` choices. Providers that return fewer choices are asked again
+    /// with different seeds. Only valid replies are returned as votes, but invalid ones still
+    /// count in the denominator of the confidence, see [`TaskSelection::from_votes`].
+    async fn sample_votes(&self, request: ChatRequest) -> eyre::Result<Vec<TaskType>> {
+        let request = request.with_response_format(ResponseFormat::JsonObject);
+        let parse = |content: &str| match parse_structured::<TaskReply>(content) {
+            Ok(TaskReply { task }) => Some(task),
+            Err(e) => {
+                log::debug!("Invalid task reply {content:?}: {e}");
+                None
+            }
+        };
+
+        let completion = self
+            .client
+            .complete(request.clone().with_n(self.samples))
+            .await?;
+        // providers return the first choice only, if they ignore `n`
+        let choices = if completion.choices.is_empty() {
+            vec![completion.message]
+        } else {
+            completion.choices
+        };
+        let mut replies = choices.len();
+        let mut votes = choices
+            .iter()
+            .filter_map(|choice| parse(&choice.content))
+            .collect::<Vec<_>>();
+
+        while replies < self.samples {
+            // the same seed would give the same reply
+            let seed = request.random_seed.map(|seed| seed + replies as i64);
+            let completion = self
+                .client
+                .complete(request.clone().with_random_seed(seed))
+                .await?;
+            votes.extend(parse(completion.content()));
+            replies += 1;
+        }
 
-        Ok(task_type)
+        Ok(votes)
     }
 }
+
+#[test]
+fn test_task_selection_from_votes() {
+    use TaskType::*;
+
+    let selection = TaskSelection::from_votes(&[Help, Note, Note], 3).unwrap();
+    assert_eq!(selection.task, Note);
+    assert!((selection.confidence - 2.0 / 3.0).abs() < 1e-6);
+
+    // the first vote wins a tie
+    let selection = TaskSelection::from_votes(&[Help, Note], 2).unwrap();
+    assert_eq!(selection.task, Help);
+    assert_eq!(selection.confidence, 0.5);
+
+    // invalid replies are not votes, but they are samples
+    let selection = TaskSelection::from_votes(&[Note], 5).unwrap();
+    assert_eq!(selection.task, Note);
+    assert!((selection.confidence - 0.2).abs() < 1e-6);
+
+    assert!(TaskSelection::from_votes(&[], 3).is_none());
+}
@@ -1,15 +1,20 @@
-use bot::{BotArgs, MessageHandlerContext, TgBot};
+use bot::{spawn_metrics_server, BotArgs, MessageHandlerContext, TgBot};
 use clap::Parser;
 use dotenvy::dotenv;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use teloxide::{
+    dispatching::{Dispatcher, UpdateFilterExt},
+    dptree,
     requests::RequesterExt,
-    types::{Message as TgMessage, ParseMode},
+    types::{CallbackQuery, Message as TgMessage, ParseMode, Update},
     Bot,
 };
 
+/// How often sessions of idle chats are removed.
+const SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 #[tokio::main]
-async fn main() {
+async fn main() -> eyre::Result<()> {
     if dotenv().is_ok() {
         log::debug!("Loaded .env file");
     }
@@ -19,19 +24,66 @@ async fn main() {
 
     log::info!("Starting bot with args: {:?}", args);
 
-    let bot = Bot::new(&args.secrets.telegram_token);
+    let http_client = args
+        .http
+        .telegram_http_settings()?
+        .apply(teloxide::net::default_reqwest_settings())?
+        .build()?;
+    let bot = Bot::with_client(&args.secrets.telegram_token, http_client);
     let bot = bot.parse_mode(ParseMode::MarkdownV2);
 
-    let ctx = Arc::new(MessageHandlerContext::new(&args));
+    let ctx = MessageHandlerContext::new(&args)?;
+    ctx.validate_models().await?;
 
-    teloxide::repl(bot, move |bot: TgBot, user_msg: TgMessage| {
-        let ctx = ctx.clone();
+    if let Some(llm_cache) = &ctx.llm_cache {
+        let expired_completions = llm_cache.remove_expired();
+        if expired_completions > 0 {
+            log::info!("Removed {expired_completions} expired LLM cache files");
+        }
+    }
+    let ctx = Arc::new(ctx);
+
+    tokio::spawn(remove_expired_sessions(ctx.clone()));
+
+    if let Some(addr) = args.metrics.metrics_addr {
+        spawn_metrics_server(addr, ctx.clone())?;
+    }
 
-        async move {
-            ctx.handle_message(&bot, user_msg).await?;
+    let handler = dptree::entry()
+        .branch(Update::filter_message().endpoint(
+            |ctx: Arc<MessageHandlerContext>, bot: TgBot, user_msg: TgMessage| async move {
+                ctx.handle_message(&bot, user_msg).await
+            },
+        ))
+        .branch(Update::filter_callback_query().endpoint(
+            |ctx: Arc<MessageHandlerContext>, bot: TgBot, query: CallbackQuery| async move {
+                ctx.handle_callback_query(&bot, query).await
+            },
+        ));
 
-            Ok(())
+    Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![ctx])
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
+
+    Ok(())
+}
+
+/// Remove sessions of chats that stayed idle longer than the timeout, at startup and then
+/// periodically, so sessions of chats that never come back don't stay on disk.
+async fn remove_expired_sessions(ctx: Arc<MessageHandlerContext>) {
+    let mut interval = tokio::time::interval(SESSION_CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let ctx = ctx.clone();
+        match tokio::task::spawn_blocking(move || ctx.sessions.remove_expired()).await {
+            Ok(0) => {}
+            Ok(removed) => log::info!("Removed {removed} expired chat sessions"),
+            Err(e) => log::error!("Failed to remove expired chat sessions: {e}"),
         }
-    })
-    .await;
+    }
 }
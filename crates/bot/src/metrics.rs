@@ -0,0 +1,135 @@
+use crate::MessageHandlerContext;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use llm_client::{CacheStats, QueueDepth};
+use std::{convert::Infallible, fmt::Write, net::SocketAddr, sync::Arc};
+
+const METRICS_PATH: &str = "/metrics";
+
+/// Serve metrics of the bot in the Prometheus text format at `/metrics` of the address.
+/// Fails if the address can't be bound, otherwise the server runs in the background.
+/// Returns the bound address.
+pub fn spawn_metrics_server(
+    addr: SocketAddr,
+    ctx: Arc<MessageHandlerContext>,
+) -> eyre::Result<SocketAddr> {
+    let make_service = make_service_fn(move |_| {
+        let ctx = ctx.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let ctx = ctx.clone();
+                async move { Ok::<_, Infallible>(handle(&ctx, &request)) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)
+        .map_err(|e| eyre::eyre!("failed to bind metrics server to {addr}: {e}"))?
+        .serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Metrics server failed: {e}");
+        }
+    });
+
+    log::info!("Serving metrics at http://{addr}{METRICS_PATH}");
+
+    Ok(addr)
+}
+
+fn handle(ctx: &MessageHandlerContext, request: &Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let queue_depth = ctx
+        .rate_limiter
+        .as_ref()
+        .map(|limiter| limiter.queue_depth());
+    let cache_stats = ctx.llm_cache.as_ref().map(|cache| cache.stats());
+
+    let mut response = Response::new(format_metrics(queue_depth, cache_stats).into());
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        "text/plain; version=0.0.4"
+            .parse()
+            .expect("header value is valid"),
+    );
+    response
+}
+
+/// Format the metrics in the Prometheus text format.
+/// Metrics of the rate limiter and the cache are only present if they are enabled.
+pub fn format_metrics(queue_depth: Option<QueueDepth>, cache_stats: Option<CacheStats>) -> String {
+    let mut metrics = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+        writeln!(metrics, "# HELP {name} {help}").expect("writing to a string can't fail");
+        writeln!(metrics, "# TYPE {name} {kind}").expect("writing to a string can't fail");
+        for (labels, value) in samples {
+            writeln!(metrics, "{name}{labels} {value}").expect("writing to a string can't fail");
+        }
+    };
+
+    if let Some(depth) = queue_depth {
+        metric(
+            "llm_queued_requests",
+            "gauge",
+            "LLM requests waiting for the rate limiter.",
+            &[
+                (r#"{priority="interactive"}"#, depth.interactive as u64),
+                (r#"{priority="background"}"#, depth.background as u64),
+            ],
+        );
+        metric(
+            "llm_in_flight_requests",
+            "gauge",
+            "LLM requests sent and not completed yet.",
+            &[("", depth.in_flight as u64)],
+        );
+    }
+
+    if let Some(stats) = cache_stats {
+        metric(
+            "llm_cache_hits_total",
+            "counter",
+            "LLM completions served from the cache.",
+            &[("", stats.hits)],
+        );
+        metric(
+            "llm_cache_misses_total",
+            "counter",
+            "Cacheable LLM requests sent to the provider.",
+            &[("", stats.misses)],
+        );
+        metric(
+            "llm_cache_entries",
+            "gauge",
+            "LLM completions kept in memory.",
+            &[("", stats.entries as u64)],
+        );
+    }
+
+    metrics
+}
+
+#[test]
+fn test_format_metrics() {
+    let depth = QueueDepth {
+        interactive: 2,
+        background: 1,
+        in_flight: 3,
+    };
+    let metrics = format_metrics(Some(depth), None);
+
+    assert!(metrics.contains("# TYPE llm_queued_requests gauge\n"));
+    assert!(metrics.contains("llm_queued_requests{priority=\"interactive\"} 2\n"));
+    assert!(metrics.contains("llm_queued_requests{priority=\"background\"} 1\n"));
+    assert!(metrics.contains("llm_in_flight_requests 3\n"));
+    assert!(!metrics.contains("llm_cache"));
+}
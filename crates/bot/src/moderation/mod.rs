@@ -0,0 +1,5 @@
+mod policy;
+mod stage;
+
+pub use policy::*;
+pub use stage::*;
@@ -0,0 +1,160 @@
+use clap::ValueEnum;
+use llm_client::{Moderation, ModerationCategory};
+use serde::Serialize;
+use std::{collections::HashMap, str::FromStr};
+
+/// What to do with content of a flagged category, from the mildest to the strictest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    /// Process the content as usual, without an audit entry.
+    Allow,
+    /// Process the content and warn the user about it.
+    Warn,
+    /// Process the content and add `#sensitive` to the tags of notes.
+    Sensitive,
+    /// Don't process the content.
+    Refuse,
+}
+
+/// Actions for flagged categories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModerationPolicy {
+    actions: HashMap<ModerationCategory, ModerationAction>,
+    default_action: ModerationAction,
+}
+
+impl Default for ModerationPolicy {
+    fn default() -> Self {
+        Self::new(ModerationAction::Warn)
+    }
+}
+
+impl ModerationPolicy {
+    /// Create a policy with the same action for all categories.
+    pub fn new(default_action: ModerationAction) -> Self {
+        Self {
+            actions: HashMap::new(),
+            default_action,
+        }
+    }
+
+    pub fn with_action(mut self, category: ModerationCategory, action: ModerationAction) -> Self {
+        self.actions.insert(category, action);
+        self
+    }
+
+    pub fn action(&self, category: ModerationCategory) -> ModerationAction {
+        self.actions
+            .get(&category)
+            .copied()
+            .unwrap_or(self.default_action)
+    }
+
+    /// Decide what to do with the checked content, the strictest action of its categories wins.
+    pub fn verdict(&self, moderation: &Moderation) -> ModerationVerdict {
+        let categories = moderation
+            .flagged
+            .iter()
+            .copied()
+            .filter(|category| self.action(*category) != ModerationAction::Allow)
+            .collect::<Vec<_>>();
+        let action = categories
+            .iter()
+            .map(|category| self.action(*category))
+            .max()
+            .unwrap_or(ModerationAction::Allow);
+
+        ModerationVerdict { action, categories }
+    }
+}
+
+/// Parse a policy from a comma separated list of `category=action`,
+/// e.g. `*=allow,selfharm=warn,violence_and_threats=refuse`.
+/// `*` sets the action of the categories that are not listed, `warn` if it's not set.
+impl FromStr for ModerationPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::default();
+
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (category, action) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("invalid action {entry:?}, expected `category=action`"))?;
+            let action = ModerationAction::from_str(action.trim(), true)?;
+
+            match category.trim() {
+                "*" => policy.default_action = action,
+                category => policy = policy.with_action(category.parse()?, action),
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+/// What to do with checked content and which of its categories caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModerationVerdict {
+    pub action: ModerationAction,
+    /// Flagged categories that are not allowed.
+    pub categories: Vec<ModerationCategory>,
+}
+
+impl ModerationVerdict {
+    pub fn allow() -> Self {
+        Self {
+            action: ModerationAction::Allow,
+            categories: Vec::new(),
+        }
+    }
+
+    pub fn is_flagged(&self) -> bool {
+        !self.categories.is_empty()
+    }
+
+    /// Warning shown to the user, e.g. `Content may be sensitive: selfharm.`.
+    pub fn warning(&self) -> String {
+        let categories = self
+            .categories
+            .iter()
+            .map(|category| category.as_str().replace('_', " "))
+            .collect::<Vec<_>>();
+
+        format!("Content may be sensitive: {}.", categories.join(", "))
+    }
+}
+
+#[test]
+fn test_moderation_policy() {
+    let policy: ModerationPolicy = "*=allow, selfharm=warn, pii=sensitive, law=refuse"
+        .parse()
+        .unwrap();
+    let moderation = |flagged: &[ModerationCategory]| Moderation {
+        flagged: flagged.to_vec(),
+        ..Default::default()
+    };
+
+    let verdict = policy.verdict(&moderation(&[
+        ModerationCategory::Health,
+        ModerationCategory::Selfharm,
+        ModerationCategory::Pii,
+    ]));
+    assert_eq!(verdict.action, ModerationAction::Sensitive);
+    assert_eq!(
+        verdict.categories,
+        [ModerationCategory::Selfharm, ModerationCategory::Pii]
+    );
+    assert_eq!(
+        verdict.warning(),
+        "Content may be sensitive: selfharm, pii."
+    );
+
+    let verdict = policy.verdict(&moderation(&[ModerationCategory::Health]));
+    assert_eq!(verdict, ModerationVerdict::allow());
+
+    assert!("sexual".parse::<ModerationPolicy>().is_err());
+    assert!("sexual=ban".parse::<ModerationPolicy>().is_err());
+    assert!("unknown=warn".parse::<ModerationPolicy>().is_err());
+}
@@ -0,0 +1,133 @@
+use crate::{ModerationAction, ModerationPolicy, ModerationVerdict};
+use chrono::Utc;
+use llm_client::{ModerationCategory, Moderator};
+use serde::Serialize;
+use std::{io::Write, path::PathBuf, sync::Arc};
+use teloxide::types::Message as TgMessage;
+
+/// Longest part of flagged content saved to the audit log, in chars.
+const AUDIT_TEXT_CHARS: usize = 500;
+
+/// Where the checked content comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentSource {
+    /// Text, transcript or photo caption sent by the user.
+    UserMessage,
+    /// Help generated by the model.
+    HelpReply,
+}
+
+/// Line of the audit log.
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    time: String,
+    chat_id: i64,
+    user_id: Option<u64>,
+    message_id: i32,
+    source: ContentSource,
+    action: ModerationAction,
+    categories: &'a [ModerationCategory],
+    text: String,
+}
+
+/// Check of user messages and generated replies with a moderator.
+/// Flagged content is logged without the text and, if an audit log is set, appended to it
+/// as a JSON line.
+#[derive(Debug)]
+pub struct ModerationStage {
+    moderator: Arc<dyn Moderator>,
+    policy: ModerationPolicy,
+    audit_log: Option<PathBuf>,
+}
+
+impl ModerationStage {
+    pub fn new(moderator: Arc<dyn Moderator>) -> Self {
+        Self {
+            moderator,
+            policy: ModerationPolicy::default(),
+            audit_log: None,
+        }
+    }
+
+    /// Set the actions for flagged categories. Default is to warn about all of them.
+    pub fn with_policy(mut self, policy: ModerationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set the file audit entries are appended to.
+    pub fn with_audit_log(mut self, audit_log: impl Into<Option<PathBuf>>) -> Self {
+        self.audit_log = audit_log.into();
+        self
+    }
+
+    /// Decide what to do with the content related to the message.
+    /// Content is allowed if the moderator fails, so its outage doesn't stop the bot.
+    pub async fn check(
+        &self,
+        user_msg: &TgMessage,
+        source: ContentSource,
+        text: &str,
+    ) -> ModerationVerdict {
+        let moderation = match self.moderator.moderate(text).await {
+            Ok(moderation) => moderation,
+            Err(e) => {
+                log::warn!("Failed to moderate {source:?}, allowing it: {e}");
+                return ModerationVerdict::allow();
+            }
+        };
+
+        let verdict = self.policy.verdict(&moderation);
+        if verdict.is_flagged() {
+            self.audit(user_msg, source, &verdict, text).await;
+        }
+
+        verdict
+    }
+
+    async fn audit(
+        &self,
+        user_msg: &TgMessage,
+        source: ContentSource,
+        verdict: &ModerationVerdict,
+        text: &str,
+    ) {
+        let entry = AuditEntry {
+            time: Utc::now().to_rfc3339(),
+            chat_id: user_msg.chat.id.0,
+            user_id: user_msg.from().map(|user| user.id.0),
+            message_id: user_msg.id.0,
+            source,
+            action: verdict.action,
+            categories: &verdict.categories,
+            text: text.chars().take(AUDIT_TEXT_CHARS).collect(),
+        };
+        // the text itself only goes to the audit log, which is meant to be kept private
+        log::warn!(
+            "Flagged {source:?} in chat {}: {:?}, {:?}",
+            entry.chat_id,
+            entry.action,
+            entry.categories
+        );
+
+        let Some(path) = self.audit_log.clone() else {
+            return;
+        };
+        let line = serde_json::to_string(&entry).expect("audit entry is serializable") + "\n";
+
+        let file = path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .and_then(|mut file| file.write_all(line.as_bytes()))
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.into()));
+        if let Err(e) = result {
+            log::error!("Failed to write moderation audit log {path:?}: {e}");
+        }
+    }
+}
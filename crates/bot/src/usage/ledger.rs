@@ -0,0 +1,177 @@
+use chrono::NaiveDate;
+use llm_client::Usage;
+use std::{collections::HashMap, hash::Hash, ops::AddAssign, sync::Mutex};
+use teloxide::types::{ChatId, UserId};
+
+/// Telegram user and chat the LLM work is done for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UsageKey {
+    pub user_id: Option<UserId>,
+    pub chat_id: ChatId,
+}
+
+/// Tokens, cost and number of requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageStats {
+    pub usage: Usage,
+    pub cost: f64,
+    pub requests: u64,
+}
+
+impl AddAssign for UsageStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.usage += rhs.usage;
+        self.cost += rhs.cost;
+        self.requests += rhs.requests;
+    }
+}
+
+/// Usage of the current day and of all time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UsageSummary {
+    day: NaiveDate,
+    pub today: UsageStats,
+    pub total: UsageStats,
+}
+
+impl UsageSummary {
+    fn new(day: NaiveDate) -> Self {
+        Self {
+            day,
+            today: UsageStats::default(),
+            total: UsageStats::default(),
+        }
+    }
+
+    /// Reset the daily stats if the day has changed.
+    fn roll_to(&mut self, day: NaiveDate) {
+        if self.day != day {
+            self.day = day;
+            self.today = UsageStats::default();
+        }
+    }
+}
+
+/// Limits of the daily usage of a single user.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UsageBudget {
+    pub daily_cost: Option<f64>,
+    pub daily_tokens: Option<u64>,
+}
+
+impl UsageBudget {
+    pub fn is_exceeded(&self, today: &UsageStats) -> bool {
+        self.daily_cost.is_some_and(|limit| today.cost >= limit)
+            || self
+                .daily_tokens
+                .is_some_and(|limit| today.usage.total_tokens >= limit)
+    }
+}
+
+/// In-memory LLM usage of Telegram users and chats.
+#[derive(Debug, Default)]
+pub struct UsageLedger {
+    users: Mutex<HashMap<UserId, UsageSummary>>,
+    chats: Mutex<HashMap<ChatId, UsageSummary>>,
+}
+
+impl UsageLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add usage of a single request.
+    pub fn record(&self, key: UsageKey, usage: Usage, cost: f64) {
+        self.record_at(key, usage, cost, today());
+    }
+
+    fn record_at(&self, key: UsageKey, usage: Usage, cost: f64, day: NaiveDate) {
+        let stats = UsageStats {
+            usage,
+            cost,
+            requests: 1,
+        };
+
+        if let Some(user_id) = key.user_id {
+            add_stats(&self.users, user_id, stats, day);
+        }
+        add_stats(&self.chats, key.chat_id, stats, day);
+    }
+
+    pub fn user_usage(&self, user_id: UserId) -> Option<UsageSummary> {
+        get_stats(&self.users, user_id, today())
+    }
+
+    pub fn chat_usage(&self, chat_id: ChatId) -> Option<UsageSummary> {
+        get_stats(&self.chats, chat_id, today())
+    }
+
+    /// Whether the user has used up the daily budget.
+    pub fn is_budget_exceeded(&self, user_id: UserId, budget: &UsageBudget) -> bool {
+        self.user_usage(user_id)
+            .is_some_and(|summary| budget.is_exceeded(&summary.today))
+    }
+}
+
+fn today() -> NaiveDate {
+    chrono::Utc::now().date_naive()
+}
+
+fn add_stats<K: Eq + Hash>(
+    map: &Mutex<HashMap<K, UsageSummary>>,
+    key: K,
+    stats: UsageStats,
+    day: NaiveDate,
+) {
+    let mut map = map.lock().unwrap_or_else(|e| e.into_inner());
+    let summary = map.entry(key).or_insert_with(|| UsageSummary::new(day));
+
+    summary.roll_to(day);
+    summary.today += stats;
+    summary.total += stats;
+}
+
+fn get_stats<K: Eq + Hash>(
+    map: &Mutex<HashMap<K, UsageSummary>>,
+    key: K,
+    day: NaiveDate,
+) -> Option<UsageSummary> {
+    let map = map.lock().unwrap_or_else(|e| e.into_inner());
+    let mut summary = *map.get(&key)?;
+    summary.roll_to(day);
+
+    Some(summary)
+}
+
+#[test]
+fn test_ledger_daily_budget() {
+    let ledger = UsageLedger::new();
+    let key = UsageKey {
+        user_id: Some(UserId(1)),
+        chat_id: ChatId(2),
+    };
+    let usage = Usage {
+        prompt_tokens: 80,
+        completion_tokens: 20,
+        total_tokens: 100,
+    };
+    let budget = UsageBudget {
+        daily_cost: Some(1.0),
+        daily_tokens: None,
+    };
+
+    let yesterday = today().pred_opt().unwrap();
+    ledger.record_at(key, usage, 2.0, yesterday);
+    assert!(!ledger.is_budget_exceeded(UserId(1), &budget));
+
+    ledger.record(key, usage, 0.5);
+    assert!(!ledger.is_budget_exceeded(UserId(1), &budget));
+    ledger.record(key, usage, 0.5);
+    assert!(ledger.is_budget_exceeded(UserId(1), &budget));
+
+    let summary = ledger.chat_usage(ChatId(2)).unwrap();
+    assert_eq!(summary.today.requests, 2);
+    assert_eq!(summary.total.requests, 3);
+    assert_eq!(summary.total.usage.total_tokens, 300);
+    assert!(ledger.user_usage(UserId(3)).is_none());
+}
@@ -0,0 +1,5 @@
+mod ledger;
+mod tracking_client;
+
+pub use ledger::*;
+pub use tracking_client::*;
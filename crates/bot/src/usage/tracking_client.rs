@@ -0,0 +1,117 @@
+use crate::{UsageKey, UsageLedger};
+use futures::StreamExt;
+use llm_client::{
+    ChatRequest, Completion, ContentStream, LlmClient, LlmResult, ModelInfo, PriceTable,
+    StreamEvent, Usage,
+};
+use std::{future::Future, sync::Arc};
+
+tokio::task_local! {
+    /// User and chat the current task works for.
+    static USAGE_KEY: UsageKey;
+}
+
+/// Run the future with all LLM usage inside it accounted to the given user and chat.
+pub async fn with_usage_key<F: Future>(key: UsageKey, f: F) -> F::Output {
+    USAGE_KEY.scope(key, f).await
+}
+
+/// Client that records usage of every request made inside [`with_usage_key`] to the ledger.
+#[derive(Debug)]
+pub struct UsageTrackingClient {
+    inner: Arc<dyn LlmClient>,
+    ledger: Arc<UsageLedger>,
+    prices: Arc<PriceTable>,
+}
+
+impl UsageTrackingClient {
+    pub fn new(inner: Arc<dyn LlmClient>, ledger: Arc<UsageLedger>, prices: PriceTable) -> Self {
+        Self {
+            inner,
+            ledger,
+            prices: Arc::new(prices),
+        }
+    }
+
+    /// Key is taken when the request is made, so streams are accounted correctly
+    /// even if they are read by another task.
+    fn recorder(&self, request: &ChatRequest) -> UsageRecorder {
+        let requested_model = request
+            .model
+            .clone()
+            .unwrap_or_else(|| self.inner.default_model().to_string());
+
+        UsageRecorder {
+            key: USAGE_KEY.try_with(|key| *key).ok(),
+            ledger: self.ledger.clone(),
+            prices: self.prices.clone(),
+            requested_model,
+        }
+    }
+}
+
+/// Records usage of a single request.
+#[derive(Debug, Clone)]
+struct UsageRecorder {
+    key: Option<UsageKey>,
+    ledger: Arc<UsageLedger>,
+    prices: Arc<PriceTable>,
+    requested_model: String,
+}
+
+impl UsageRecorder {
+    /// `model` is the model reported by the provider, it is used if the price of the
+    /// requested model is unknown (e.g. the provider resolved an alias to a versioned model).
+    fn record(&self, model: Option<&str>, usage: Usage) {
+        let cost = self
+            .prices
+            .cost(&self.requested_model, &usage)
+            .or_else(|| model.and_then(|model| self.prices.cost(model, &usage)))
+            .unwrap_or_default();
+
+        let Some(key) = self.key else {
+            log::debug!("LLM usage outside of any user scope: {usage:?}");
+            return;
+        };
+
+        log::debug!("LLM usage of {key:?}: {usage:?}, cost {cost}");
+        self.ledger.record(key, usage, cost);
+    }
+}
+
+#[async_trait::async_trait]
+impl LlmClient for UsageTrackingClient {
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion> {
+        let recorder = self.recorder(&request);
+        let completion = self.inner.chat(request).await?;
+
+        recorder.record(Some(&completion.model), completion.usage);
+
+        Ok(completion)
+    }
+
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream> {
+        let recorder = self.recorder(&request);
+        let stream = self.inner.chat_stream(request).await?;
+
+        let stream = stream.inspect(move |event| {
+            if let Ok(StreamEvent::Usage(usage)) = event {
+                recorder.record(None, *usage);
+            }
+        });
+
+        Ok(stream.boxed())
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn default_max_tokens(&self) -> Option<usize> {
+        self.inner.default_max_tokens()
+    }
+
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        self.inner.list_models().await
+    }
+}
@@ -0,0 +1,11 @@
+use crate::TgBot;
+use teloxide::{net::Download, requests::Requester, RequestError};
+
+/// Download the content of a file sent to the bot.
+pub async fn download_file(bot: &TgBot, file_id: &str) -> Result<Vec<u8>, RequestError> {
+    let file = bot.get_file(file_id).await?;
+    let mut data = Vec::new();
+    bot.download_file(&file.path, &mut data).await?;
+
+    Ok(data)
+}
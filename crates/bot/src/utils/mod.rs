@@ -1,7 +1,7 @@
+mod download;
 mod markdown;
-mod parse_template;
 mod send_helpers;
 
+pub use download::*;
 pub use markdown::*;
-pub use parse_template::*;
 pub use send_helpers::*;
@@ -0,0 +1,201 @@
+//! Tests of the generators against a local fake of the Mistral API.
+
+use bot::{HelpDraft, HelpGenerator, TagsGenerator, TaskSelector, TaskType};
+use fake_llm_server::{FakeLlmServer, ScriptedReply};
+use futures::TryStreamExt;
+use llm_client::{ChatMessage, ChatSession, ImageUrl, LlmClient, MistralClient, RetryPolicy};
+use std::{sync::Arc, time::Duration};
+
+fn llm_client(server: &FakeLlmServer) -> Arc<dyn LlmClient> {
+    let retry_policy = RetryPolicy::default().with_initial_backoff(Duration::from_millis(10));
+
+    Arc::new(
+        MistralClient::new("token")
+            .with_api_url(server.api_url())
+            .with_retry_policy(retry_policy),
+    )
+}
+
+#[tokio::test]
+async fn test_task_selector_reasks_invalid_reply() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::content(r#"{"task": "shopping"}"#),
+        ScriptedReply::content(r#"{"task": "note"}"#),
+    ]);
+
+    let task = TaskSelector::new(llm_client(&server))
+        .select_task("Buy milk")
+        .await
+        .unwrap();
+
+    assert_eq!(task, TaskType::Note);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["response_format"]["type"], "json_object");
+}
+
+#[tokio::test]
+async fn test_task_selector_votes_with_different_seeds() {
+    let server = FakeLlmServer::start().await;
+    // the fake server ignores `n` and returns a single choice
+    server.push_replies([
+        ScriptedReply::content(r#"{"task": "note"}"#),
+        ScriptedReply::content(r#"{"task": "help"}"#),
+        ScriptedReply::content(r#"{"task": "note"}"#),
+    ]);
+
+    let selection = TaskSelector::new(llm_client(&server))
+        .with_random_seed(123)
+        .with_samples(3)
+        .select_task_with_confidence("What is the meaning of life?")
+        .await
+        .unwrap();
+
+    assert_eq!(selection.task, TaskType::Note);
+    assert!((selection.confidence - 2.0 / 3.0).abs() < 1e-6);
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0]["n"], 3);
+    assert_eq!(requests[1]["random_seed"], 124);
+    assert_eq!(requests[2]["random_seed"], 125);
+}
+
+#[tokio::test]
+async fn test_task_selector_invalid_replies_lower_confidence() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::content(r#"{"task": "note"}"#),
+        ScriptedReply::content("not json"),
+        ScriptedReply::content(r#"{"task": "shopping"}"#),
+    ]);
+
+    let selection = TaskSelector::new(llm_client(&server))
+        .with_samples(3)
+        .select_task_with_confidence("Buy milk")
+        .await
+        .unwrap();
+
+    assert_eq!(selection.task, TaskType::Note);
+    assert!((selection.confidence - 1.0 / 3.0).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn test_tags_generator_survives_rate_limit() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::rate_limited(0),
+        ScriptedReply::content(r#"{"tags": ["must_watch", "movie", "sci-fi"]}"#),
+    ]);
+
+    let tags = TagsGenerator::new(llm_client(&server))
+        .generate_tags_md("Watch Interstellar")
+        .await
+        .unwrap();
+
+    assert_eq!(tags, r"\#must\_watch \#movie \#sci\_fi");
+}
+
+#[tokio::test]
+async fn test_help_generator_strips_end_marker() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::content(
+        "Send me any note and I will tag it.[[END]]",
+    ));
+
+    let help = HelpGenerator::new(llm_client(&server))
+        .generate_help(&ChatSession::new(), "What can you do?")
+        .await
+        .unwrap();
+
+    assert_eq!(help, "Send me any note and I will tag it.");
+}
+
+#[tokio::test]
+async fn test_help_generator_streams_truncated_help() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::truncated("Send me any note and"));
+
+    let responses: Vec<HelpDraft> = HelpGenerator::new(llm_client(&server))
+        .stream_help(&ChatSession::new(), "What can you do?")
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    let last = responses.last().unwrap();
+    assert_eq!(last.text, "Send me any note and…");
+    assert!(last.truncated);
+}
+
+#[tokio::test]
+async fn test_help_generator_continues_session() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::content("Just send it as a message."));
+    let mut session = ChatSession::new();
+    session.push(ChatMessage::user("What can you do?"));
+    session.push(ChatMessage::assistant("I can tag your notes."));
+
+    HelpGenerator::new(llm_client(&server))
+        .generate_help(&session, "How do I send one?")
+        .await
+        .unwrap();
+
+    let requests = server.requests();
+    let messages = requests[0]["messages"].as_array().unwrap();
+    let last = messages
+        .iter()
+        .rev()
+        .take(3)
+        .map(|message| message["content"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        last,
+        [
+            "How do I send one?",
+            "I can tag your notes.",
+            "What can you do?"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_tags_generator_retries_truncated_reply() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::truncated(r#"{"tags": ["shopping_list", "gro"#),
+        ScriptedReply::content(r#"{"tags": ["shopping_list", "grocery"]}"#),
+    ]);
+
+    let tags = TagsGenerator::new(llm_client(&server))
+        .generate_tags("Buy milk")
+        .await
+        .unwrap();
+
+    assert_eq!(tags.to_string(), "#shopping_list #grocery");
+    let requests = server.requests();
+    assert_eq!(
+        requests[1]["max_tokens"],
+        requests[0]["max_tokens"].as_u64().unwrap() * 2
+    );
+}
+
+#[tokio::test]
+async fn test_tags_generator_sends_photo_to_vision_model() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::content(r#"{"tags": ["pet", "cat"]}"#));
+
+    let tags = TagsGenerator::new(llm_client(&server))
+        .with_vision_model("pixtral-12b-2409")
+        .generate_image_tags_md(ImageUrl::new("https://example.com/cat.jpg"), Some("Tom"))
+        .await
+        .unwrap();
+
+    assert_eq!(tags, r"\#pet \#cat");
+    let requests = server.requests();
+    assert_eq!(requests[0]["model"], "pixtral-12b-2409");
+    let parts = requests[0]["messages"].as_array().unwrap().last().unwrap()["content"].clone();
+    assert_eq!(parts[0]["text"], "My note to generate tags for:\nTom");
+    assert_eq!(parts[1]["image_url"]["url"], "https://example.com/cat.jpg");
+}
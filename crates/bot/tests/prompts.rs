@@ -0,0 +1,86 @@
+//! Prompt request-shape tests.
+//!
+//! Responses are replayed from `tests/cassettes`, so the tests run offline. A cassette is
+//! found by the hash of the request, so a test fails when a prompt, few-shot history or
+//! generator parameters change. The replies in the cassettes were written by hand, so the
+//! tests only check how the replies are parsed, not how the model answers the prompts.
+//! After changing a prompt, write new cassettes or record them with `just record-prompts`
+//! (requires `MISTRAL_TOKEN`).
+
+use bot::{HelpGenerator, TagsGenerator, TaskSelector, TaskType};
+use llm_client::{CassetteClient, ChatSession, LlmClient, MistralClient};
+use std::sync::Arc;
+
+const TEMPERATURE: f32 = 0.5;
+const RANDOM_SEED: i64 = 123;
+
+fn llm_client() -> Arc<dyn LlmClient> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cassettes");
+
+    let client = CassetteClient::from_env(dir, || {
+        dotenvy::dotenv().ok();
+        let token = std::env::var("MISTRAL_TOKEN").expect("MISTRAL_TOKEN is required to record");
+        Arc::new(MistralClient::new(token))
+    });
+
+    Arc::new(client.with_default_model("mistral-tiny"))
+}
+
+#[tokio::test]
+async fn test_task_selector() {
+    let selector = TaskSelector::new(llm_client())
+        .with_temperature(TEMPERATURE)
+        .with_random_seed(RANDOM_SEED);
+
+    let cases = [
+        ("Buy milk, eggs and bread", TaskType::Note),
+        ("Startup idea: uber for dog walking", TaskType::Note),
+        ("How do I use this bot?", TaskType::Help),
+        ("/start", TaskType::Help),
+        ("qwpeoiruty", TaskType::Unknown),
+    ];
+
+    for (text, expected) in cases {
+        let task = selector.select_task(text).await.unwrap();
+        assert_eq!(task, expected, "wrong task for {text:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_tags_generator() {
+    let generator = TagsGenerator::new(llm_client())
+        .with_temperature(TEMPERATURE)
+        .with_random_seed(RANDOM_SEED);
+
+    let cases = [
+        ("Watch Interstellar", "must_watch"),
+        ("Buy milk, eggs and bread", "shopping_list"),
+    ];
+
+    for (text, expected) in cases {
+        let tags = generator.generate_tags(text).await.unwrap();
+        assert!(
+            (2..=6).contains(&tags.len()),
+            "wrong number of tags for {text:?}: {tags}"
+        );
+        assert_eq!(tags[0], expected, "wrong category for {text:?}: {tags}");
+    }
+}
+
+#[tokio::test]
+async fn test_help_generator() {
+    let generator = HelpGenerator::new(llm_client())
+        .with_temperature(TEMPERATURE)
+        .with_random_seed(RANDOM_SEED);
+
+    let help = generator
+        .generate_help(&ChatSession::new(), "What can you do?")
+        .await
+        .unwrap();
+
+    assert!(help.contains("tags"), "unexpected help: {help}");
+    assert!(
+        !help.contains("[[END]]"),
+        "end marker is not removed: {help}"
+    );
+}
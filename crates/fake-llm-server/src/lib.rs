@@ -0,0 +1,8 @@
+//! Scriptable fake of the Mistral chat and Whisper transcription APIs for tests that must run
+//! without network access.
+
+mod reply;
+mod server;
+
+pub use reply::*;
+pub use server::*;
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+/// Reply of the server to a single `/v1/chat/completions` or `/v1/audio/transcriptions` request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptedReply {
+    /// Successful completion with the content.
+    Content(String),
+    /// Completion stopped by the token limit (`finish_reason: length`).
+    Truncated(String),
+    /// Successful response without choices.
+    EmptyChoices,
+    /// Successful response with a body that is not valid JSON.
+    MalformedJson,
+    /// 429 Too Many Requests, optionally with `Retry-After` in seconds.
+    RateLimited { retry_after: Option<u64> },
+    /// 500 Internal Server Error.
+    ServerError,
+    /// Any other reply sent after the delay.
+    Slow {
+        delay: Duration,
+        reply: Box<ScriptedReply>,
+    },
+}
+
+impl ScriptedReply {
+    pub fn content(content: impl ToString) -> Self {
+        Self::Content(content.to_string())
+    }
+
+    pub fn truncated(content: impl ToString) -> Self {
+        Self::Truncated(content.to_string())
+    }
+
+    pub fn rate_limited(retry_after: impl Into<Option<u64>>) -> Self {
+        Self::RateLimited {
+            retry_after: retry_after.into(),
+        }
+    }
+
+    /// Send the reply after the delay.
+    pub fn delayed(self, delay: Duration) -> Self {
+        Self::Slow {
+            delay,
+            reply: Box::new(self),
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use crate::ScriptedReply;
+use hyper::{
+    body::to_bytes,
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::oneshot;
+
+#[derive(Debug, Default)]
+struct State {
+    replies: VecDeque<ScriptedReply>,
+    requests: Vec<Value>,
+    transcription_requests: Vec<Vec<u8>>,
+    models: Vec<String>,
+}
+
+/// Local HTTP server implementing `/v1/chat/completions` and `/v1/models` of the Mistral
+/// (and OpenAI compatible) API, and `/v1/audio/transcriptions` of Whisper, with scripted replies.
+///
+/// Every chat or transcription request takes the next reply from the queue, with the content
+/// of the reply used as the transcript. Requests without a scripted reply fail
+/// with 400 Bad Request. The server stops when it is dropped.
+#[derive(Debug)]
+pub struct FakeLlmServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl FakeLlmServer {
+    /// Start the server on a random local port.
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(State {
+            models: vec!["mistral-tiny".to_string()],
+            ..Default::default()
+        }));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(handle(&state, request).await) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .expect("failed to bind fake LLM server")
+            .serve(make_service);
+        let addr = server.local_addr();
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = server.with_graceful_shutdown(async {
+            shutdown_rx.await.ok();
+        });
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("Fake LLM server failed: {e}");
+            }
+        });
+
+        log::debug!("Fake LLM server is listening on {addr}");
+
+        Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// Base URL of the API, to be passed to `with_api_url` of the clients.
+    pub fn api_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    /// Add a reply to the end of the queue.
+    pub fn push_reply(&self, reply: ScriptedReply) -> &Self {
+        self.lock().replies.push_back(reply);
+        self
+    }
+
+    /// Add replies to the end of the queue.
+    pub fn push_replies(&self, replies: impl IntoIterator<Item = ScriptedReply>) -> &Self {
+        self.lock().replies.extend(replies);
+        self
+    }
+
+    /// Set the models returned by `/v1/models`. Default is `mistral-tiny`.
+    pub fn set_models(&self, models: impl IntoIterator<Item = impl ToString>) -> &Self {
+        self.lock().models = models.into_iter().map(|m| m.to_string()).collect();
+        self
+    }
+
+    /// Bodies of the chat requests received so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.lock().requests.clone()
+    }
+
+    /// Raw multipart bodies of the transcription requests received so far.
+    pub fn transcription_requests(&self) -> Vec<Vec<u8>> {
+        self.lock().transcription_requests.clone()
+    }
+
+    /// Number of scripted replies that were not used yet.
+    pub fn pending_replies(&self) -> usize {
+        self.lock().replies.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for FakeLlmServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+async fn handle(state: &Mutex<State>, request: Request<Body>) -> Response<Body> {
+    let lock = || state.lock().unwrap_or_else(|e| e.into_inner());
+
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/v1/models") => {
+            let data = lock()
+                .models
+                .iter()
+                .map(|id| json!({ "id": id, "object": "model", "owned_by": "fake", "created": 0 }))
+                .collect::<Vec<_>>();
+
+            json_response(StatusCode::OK, json!({ "object": "list", "data": data }))
+        }
+        (&Method::POST, "/v1/chat/completions") => {
+            let body = match to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+            let body: Value = match serde_json::from_slice(&body) {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+
+            let reply = {
+                let mut state = lock();
+                state.requests.push(body.clone());
+                state.replies.pop_front()
+            };
+
+            match reply {
+                Some(reply) => scripted_response(&body, reply).await,
+                None => error_response(StatusCode::BAD_REQUEST, "no scripted reply left"),
+            }
+        }
+        (&Method::POST, "/v1/audio/transcriptions") => {
+            let body = match to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+
+            let reply = {
+                let mut state = lock();
+                state.transcription_requests.push(body.to_vec());
+                state.replies.pop_front()
+            };
+
+            match reply {
+                Some(reply) => match wait_delays(reply).await {
+                    ScriptedReply::Content(text) => {
+                        json_response(StatusCode::OK, json!({ "text": text }))
+                    }
+                    // failures are the same as for chat requests
+                    reply => scripted_response(&Value::Null, reply).await,
+                },
+                None => error_response(StatusCode::BAD_REQUEST, "no scripted reply left"),
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+/// Wait for the delays of a slow reply and return the reply sent after them.
+async fn wait_delays(mut reply: ScriptedReply) -> ScriptedReply {
+    while let ScriptedReply::Slow { delay, reply: next } = reply {
+        tokio::time::sleep(delay).await;
+        reply = *next;
+    }
+
+    reply
+}
+
+async fn scripted_response(request: &Value, reply: ScriptedReply) -> Response<Body> {
+    let reply = wait_delays(reply).await;
+
+    let model = request["model"].as_str().unwrap_or("mistral-tiny");
+    let stream = request["stream"].as_bool().unwrap_or(false);
+
+    let (content, finish_reason) = match reply {
+        ScriptedReply::Content(content) => (content, "stop"),
+        ScriptedReply::Truncated(content) => (content, "length"),
+        ScriptedReply::EmptyChoices => {
+            let body = json!({
+                "id": "fake",
+                "object": "chat.completion",
+                "created": 0,
+                "model": model,
+                "choices": [],
+                "usage": usage(request, ""),
+            });
+            return json_response(StatusCode::OK, body);
+        }
+        ScriptedReply::MalformedJson => {
+            return Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"id": "fake", "choices": ["#))
+                .expect("response is valid");
+        }
+        ScriptedReply::RateLimited { retry_after } => {
+            let mut response = Response::builder().status(StatusCode::TOO_MANY_REQUESTS);
+            if let Some(retry_after) = retry_after {
+                response = response.header(RETRY_AFTER, retry_after);
+            }
+            return response
+                .body(Body::from(r#"{"message": "Requests rate limit exceeded"}"#))
+                .expect("response is valid");
+        }
+        ScriptedReply::ServerError => {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal server error");
+        }
+        ScriptedReply::Slow { .. } => unreachable!("delays are resolved above"),
+    };
+
+    if stream {
+        return stream_response(request, model, &content, finish_reason);
+    }
+
+    let body = json!({
+        "id": "fake",
+        "object": "chat.completion",
+        "created": 0,
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": finish_reason,
+        }],
+        "usage": usage(request, &content),
+    });
+
+    json_response(StatusCode::OK, body)
+}
+
+/// Server-sent events with a chunk per word, usage is sent with the last chunk.
+fn stream_response(
+    request: &Value,
+    model: &str,
+    content: &str,
+    finish_reason: &str,
+) -> Response<Body> {
+    let chunk = |delta: Value, finish_reason: Value, usage: Value| {
+        let chunk = json!({
+            "id": "fake",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            "usage": usage,
+        });
+        format!("data: {chunk}\n\n")
+    };
+
+    let mut body = chunk(json!({ "role": "assistant" }), Value::Null, Value::Null);
+    for word in content.split_inclusive(' ') {
+        body += &chunk(json!({ "content": word }), Value::Null, Value::Null);
+    }
+    body += &chunk(json!({}), json!(finish_reason), usage(request, content));
+    body += "data: [DONE]\n\n";
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .body(Body::from(body))
+        .expect("response is valid")
+}
+
+/// Usage with a token per word.
+fn usage(request: &Value, content: &str) -> Value {
+    let prompt_tokens = request["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message["content"].as_str())
+        .map(|content| content.split_whitespace().count())
+        .sum::<usize>();
+    let completion_tokens = content.split_whitespace().count();
+
+    json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+    })
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("response is valid")
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "message": message }))
+}
@@ -0,0 +1,334 @@
+use crate::{
+    cassette::fnv1a, ChatRequest, Completion, ContentStream, LlmClient, LlmResult, ModelInfo, Usage,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// Hit and miss counters of [`CachingClient`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of completions in memory.
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    completion: Completion,
+    created_at: SystemTime,
+    last_used: u64,
+}
+
+/// Content of a cache file.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    key: String,
+    created_at: SystemTime,
+    completion: Completion,
+}
+
+/// Completions by request key with least recently used eviction.
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str) -> Option<&Entry> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(entry)
+    }
+
+    fn insert(
+        &mut self,
+        key: String,
+        completion: Completion,
+        created_at: SystemTime,
+        capacity: usize,
+    ) {
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            Entry {
+                completion,
+                created_at,
+                last_used: self.clock,
+            },
+        );
+
+        while self.entries.len() > capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => self.entries.remove(&key),
+                None => break,
+            };
+        }
+    }
+}
+
+/// Client that reuses completions of deterministic requests.
+///
+/// A request is cached only if it has a random seed or zero temperature, as other requests
+/// are expected to return a different completion every time. The key contains the model and
+/// all parameters of the request. Streams are never cached.
+///
+/// Completions served from the cache have zero usage, as no tokens were spent on them.
+#[derive(Debug)]
+pub struct CachingClient {
+    inner: Arc<dyn LlmClient>,
+    lru: Mutex<Lru>,
+    capacity: usize,
+    ttl: Option<Duration>,
+    dir: Option<PathBuf>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachingClient {
+    pub fn new(inner: Arc<dyn LlmClient>) -> Self {
+        Self {
+            inner,
+            lru: Default::default(),
+            capacity: 1000,
+            ttl: Some(Duration::from_secs(24 * 60 * 60)),
+            dir: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the maximum number of completions kept in memory. Default is 1000.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Set how long completions are reused. Default is 24 hours.
+    pub fn with_ttl(mut self, ttl: impl Into<Option<Duration>>) -> Self {
+        self.ttl = ttl.into();
+        self
+    }
+
+    /// Also save completions to the directory, so they are reused after restart.
+    pub fn with_dir(mut self, dir: impl Into<Option<PathBuf>>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lru().entries.len(),
+        }
+    }
+
+    /// Remove expired completions from memory and disk. Returns the number of removed files.
+    pub fn remove_expired(&self) -> usize {
+        self.lru()
+            .entries
+            .retain(|_, entry| self.is_fresh(entry.created_at));
+
+        let files = self
+            .dir
+            .as_ref()
+            .and_then(|dir| std::fs::read_dir(dir).ok());
+        let mut removed = 0;
+        for entry in files.into_iter().flatten().flatten() {
+            let path = entry.path();
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let is_expired = serde_json::from_str::<CacheFile>(&content)
+                .is_ok_and(|file| !self.is_fresh(file.created_at));
+            if is_expired && remove_file(&path) {
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    fn lru(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Key of the request, `None` if the request must not be cached.
+    fn key(&self, request: &ChatRequest) -> Option<String> {
+        let is_deterministic = request.random_seed.is_some() || request.temperature == Some(0.0);
+        if request.bypass_cache || !is_deterministic {
+            return None;
+        }
+
+        let model = request
+            .model
+            .as_deref()
+            .unwrap_or(self.inner.default_model());
+        let key = serde_json::to_string(&(model, request)).expect("request is serializable");
+
+        Some(key)
+    }
+
+    fn is_fresh(&self, created_at: SystemTime) -> bool {
+        let Some(ttl) = self.ttl else {
+            return true;
+        };
+
+        created_at.elapsed().is_ok_and(|age| age < ttl)
+    }
+
+    fn file_path(dir: &Path, key: &str) -> PathBuf {
+        dir.join(format!("{:016x}.json", fnv1a(key.as_bytes())))
+    }
+
+    fn get(&self, key: &str) -> Option<Completion> {
+        if let Some(entry) = self.lru().get(key) {
+            if self.is_fresh(entry.created_at) {
+                return Some(entry.completion.clone());
+            }
+        }
+
+        let path = Self::file_path(self.dir.as_ref()?, key);
+        let file = std::fs::read_to_string(&path).ok()?;
+        let file: CacheFile = match serde_json::from_str(&file) {
+            Ok(file) => file,
+            Err(e) => {
+                log::warn!("Invalid LLM cache file {path:?}: {e}");
+                return None;
+            }
+        };
+
+        // different keys may have the same hash
+        if file.key != key {
+            return None;
+        }
+        if !self.is_fresh(file.created_at) {
+            remove_file(&path);
+            return None;
+        }
+
+        self.lru().insert(
+            key.to_string(),
+            file.completion.clone(),
+            file.created_at,
+            self.capacity,
+        );
+
+        Some(file.completion)
+    }
+
+    fn insert(&self, key: String, completion: &Completion) {
+        let created_at = SystemTime::now();
+
+        if let Some(dir) = &self.dir {
+            let path = Self::file_path(dir, &key);
+            let file = CacheFile {
+                key: key.clone(),
+                created_at,
+                completion: completion.clone(),
+            };
+            let content = serde_json::to_string(&file).expect("completion is serializable");
+
+            let result = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, content));
+            if let Err(e) = result {
+                log::warn!("Failed to write LLM cache file {path:?}: {e}");
+            }
+        }
+
+        self.lru()
+            .insert(key, completion.clone(), created_at, self.capacity);
+    }
+}
+
+/// Remove the cache file, returns whether it was removed.
+fn remove_file(path: &Path) -> bool {
+    match std::fs::remove_file(path) {
+        Ok(()) => true,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove LLM cache file {path:?}: {e}");
+            }
+            false
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LlmClient for CachingClient {
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion> {
+        let Some(key) = self.key(&request) else {
+            return self.inner.chat(request).await;
+        };
+
+        if let Some(completion) = self.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            log::debug!("LLM cache hit: {}", completion.message.content);
+
+            return Ok(Completion {
+                usage: Usage::default(),
+                ..completion
+            });
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let completion = self.inner.chat(request).await?;
+        self.insert(key, &completion);
+
+        Ok(completion)
+    }
+
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream> {
+        self.inner.chat_stream(request).await
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn default_max_tokens(&self) -> Option<usize> {
+        self.inner.default_max_tokens()
+    }
+
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        self.inner.list_models().await
+    }
+}
+
+#[test]
+fn test_lru_eviction() {
+    let completion = |content: &str| Completion {
+        id: None,
+        message: crate::ChatMessage::assistant(content),
+        choices: Vec::new(),
+        finish_reason: Default::default(),
+        usage: Usage::default(),
+        model: "model".to_string(),
+    };
+    let now = SystemTime::now();
+    let mut lru = Lru::default();
+
+    lru.insert("a".into(), completion("a"), now, 2);
+    lru.insert("b".into(), completion("b"), now, 2);
+    assert!(lru.get("a").is_some());
+    lru.insert("c".into(), completion("c"), now, 2);
+
+    assert!(lru.get("b").is_none());
+    assert!(lru.get("a").is_some());
+    assert!(lru.get("c").is_some());
+}
@@ -0,0 +1,329 @@
+use crate::{
+    ChatRequest, Completion, ContentStream, LlmClient, LlmError, LlmResult, ModelInfo, StreamEvent,
+    Usage,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt::{self, Debug, Formatter},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Environment variable selecting the mode of [`CassetteClient::from_env`]:
+/// `record` or `replay` (default).
+pub const CASSETTE_MODE_ENV: &str = "LLM_CASSETTE_MODE";
+
+const MODELS_CASSETTE: &str = "models.json";
+
+#[derive(Clone)]
+pub enum CassetteMode {
+    /// Send requests to the inner client and save the responses.
+    Record(Arc<dyn LlmClient>),
+    /// Serve saved responses, fail on requests that were not recorded.
+    Replay,
+}
+
+impl Debug for CassetteMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Record(client) => f.debug_tuple("Record").field(client).finish(),
+            Self::Replay => f.write_str("Replay"),
+        }
+    }
+}
+
+/// Client that records request/response pairs to files ("cassettes") and replays them offline.
+///
+/// Every request is saved to `{dir}/{hash}.json`, where `hash` is computed from the whole
+/// request, so any change of the prompt, history or parameters requires a new recording.
+#[derive(Debug, Clone)]
+pub struct CassetteClient {
+    mode: CassetteMode,
+    dir: PathBuf,
+    default_model: String,
+    default_max_tokens: Option<usize>,
+}
+
+/// Content of a cassette file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Cassette {
+    request: ChatRequest,
+    stream: bool,
+    response: CassetteResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CassetteResponse {
+    Completion(Completion),
+    Stream {
+        deltas: Vec<String>,
+        usage: Option<Usage>,
+        /// The reply was cut by the token limit, see [`StreamEvent::Truncated`].
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        truncated: bool,
+    },
+}
+
+impl CassetteClient {
+    /// Record responses of the client to the directory.
+    pub fn record(client: Arc<dyn LlmClient>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            default_model: client.default_model().to_string(),
+            default_max_tokens: client.default_max_tokens(),
+            mode: CassetteMode::Record(client),
+            dir: dir.into(),
+        }
+    }
+
+    /// Replay responses recorded to the directory.
+    pub fn replay(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            mode: CassetteMode::Replay,
+            dir: dir.into(),
+            default_model: "default".to_string(),
+            default_max_tokens: None,
+        }
+    }
+
+    /// Replay responses from the directory, or record them if [`CASSETTE_MODE_ENV`] is `record`.
+    /// `client` is only called in the record mode.
+    pub fn from_env(dir: impl Into<PathBuf>, client: impl FnOnce() -> Arc<dyn LlmClient>) -> Self {
+        match std::env::var(CASSETTE_MODE_ENV).as_deref() {
+            Ok("record") => Self::record(client(), dir),
+            _ => Self::replay(dir),
+        }
+    }
+
+    /// Set the model reported by [`LlmClient::default_model`] in the replay mode.
+    /// In the record mode the default model of the inner client is used.
+    pub fn with_default_model(mut self, model: impl ToString) -> Self {
+        if let CassetteMode::Replay = self.mode {
+            self.default_model = model.to_string();
+        }
+        self
+    }
+
+    /// Set the limit reported by [`LlmClient::default_max_tokens`] in the replay mode,
+    /// it must match the recorded client for length retries to be replayed.
+    /// In the record mode the limit of the inner client is used.
+    pub fn with_default_max_tokens(mut self, max_tokens: impl Into<Option<usize>>) -> Self {
+        if let CassetteMode::Replay = self.mode {
+            self.default_max_tokens = max_tokens.into();
+        }
+        self
+    }
+
+    pub fn mode(&self) -> &CassetteMode {
+        &self.mode
+    }
+
+    fn cassette_path(&self, request: &ChatRequest, stream: bool) -> PathBuf {
+        let key = serde_json::to_string(&(request, stream)).expect("request is serializable");
+
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(key.as_bytes())))
+    }
+
+    /// Load the cassette of the request, failing if it was not recorded.
+    fn load(&self, request: &ChatRequest, stream: bool) -> LlmResult<CassetteResponse> {
+        let path = self.cassette_path(request, stream);
+        if !path.exists() {
+            log::error!("Request is not recorded to {path:?}: {request:?}");
+            return Err(LlmError::UnrecordedRequest { path });
+        }
+
+        let cassette: Cassette = read_json(&path)?;
+        Ok(cassette.response)
+    }
+
+    /// Error for a cassette whose response is of another kind than the call,
+    /// which happens if it was edited by hand or its key collides with another request.
+    fn mismatch(&self, request: &ChatRequest, stream: bool) -> LlmError {
+        let path = self.cassette_path(request, stream);
+        log::error!("Cassette {path:?} doesn't match the request: {request:?}");
+
+        LlmError::CassetteMismatch {
+            path,
+            expected: if stream { "streamed" } else { "completion" },
+        }
+    }
+
+    fn save(
+        &self,
+        request: &ChatRequest,
+        stream: bool,
+        response: CassetteResponse,
+    ) -> LlmResult<()> {
+        let cassette = Cassette {
+            request: request.clone(),
+            stream,
+            response,
+        };
+
+        write_json(&self.cassette_path(request, stream), &cassette)
+    }
+}
+
+#[async_trait::async_trait]
+impl LlmClient for CassetteClient {
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion> {
+        let client = match &self.mode {
+            CassetteMode::Record(client) => client,
+            CassetteMode::Replay => {
+                return match self.load(&request, false)? {
+                    CassetteResponse::Completion(completion) => Ok(completion),
+                    CassetteResponse::Stream { .. } => Err(self.mismatch(&request, false)),
+                };
+            }
+        };
+
+        let completion = client.chat(request.clone()).await?;
+        self.save(
+            &request,
+            false,
+            CassetteResponse::Completion(completion.clone()),
+        )?;
+
+        Ok(completion)
+    }
+
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream> {
+        let client = match &self.mode {
+            CassetteMode::Record(client) => client,
+            CassetteMode::Replay => {
+                let CassetteResponse::Stream {
+                    deltas,
+                    usage,
+                    truncated,
+                } = self.load(&request, true)?
+                else {
+                    return Err(self.mismatch(&request, true));
+                };
+
+                let events = deltas
+                    .into_iter()
+                    .map(StreamEvent::Delta)
+                    .chain(truncated.then_some(StreamEvent::Truncated))
+                    .chain(usage.map(StreamEvent::Usage))
+                    .map(LlmResult::Ok);
+
+                return Ok(stream::iter(events).boxed());
+            }
+        };
+
+        // The whole stream is read before it is saved, so it is replayed even if the caller
+        // stops reading early.
+        let events: Vec<StreamEvent> = client
+            .chat_stream(request.clone())
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut deltas = Vec::new();
+        let mut usage = None;
+        let mut truncated = false;
+        for event in &events {
+            match event {
+                StreamEvent::Delta(delta) => deltas.push(delta.clone()),
+                StreamEvent::Usage(event_usage) => usage = Some(*event_usage),
+                StreamEvent::Truncated => truncated = true,
+            }
+        }
+        let response = CassetteResponse::Stream {
+            deltas,
+            usage,
+            truncated,
+        };
+        self.save(&request, true, response)?;
+
+        Ok(stream::iter(events.into_iter().map(LlmResult::Ok)).boxed())
+    }
+
+    fn default_model(&self) -> &str {
+        &self.default_model
+    }
+
+    fn default_max_tokens(&self) -> Option<usize> {
+        self.default_max_tokens
+    }
+
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        let path = self.dir.join(MODELS_CASSETTE);
+
+        match &self.mode {
+            CassetteMode::Record(client) => {
+                let models = client.list_models().await?;
+                write_json(&path, models.as_ref())?;
+                Ok(models)
+            }
+            CassetteMode::Replay if !path.exists() => Err(LlmError::UnrecordedRequest { path }),
+            CassetteMode::Replay => Ok(Arc::new(read_json(&path)?)),
+        }
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> LlmResult<T> {
+    let content = std::fs::read_to_string(path).map_err(|source| LlmError::CassetteIo {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    serde_json::from_str(&content).map_err(|e| LlmError::malformed(e, content))
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> LlmResult<()> {
+    let io_error = |source| LlmError::CassetteIo {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let content = serde_json::to_string_pretty(value).expect("value is serializable");
+    std::fs::write(path, content + "\n").map_err(io_error)
+}
+
+/// 64-bit FNV-1a hash. Unlike std hashers, it is stable between Rust versions,
+/// so cassette names don't change when the toolchain is updated.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[test]
+fn test_fnv1a() {
+    assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+    assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+}
+
+#[tokio::test]
+async fn test_replay_mismatched_cassette() {
+    let dir = std::env::temp_dir().join(format!("llm-cassette-test-{}", std::process::id()));
+    let client = CassetteClient::replay(&dir);
+    let request = ChatRequest::new().with_user_message("Hi");
+
+    // streamed response saved under the key of a regular completion
+    let response = CassetteResponse::Stream {
+        deltas: vec!["Hello".to_string()],
+        usage: None,
+        truncated: false,
+    };
+    let cassette = Cassette {
+        request: request.clone(),
+        stream: false,
+        response,
+    };
+    write_json(&client.cassette_path(&request, false), &cassette).unwrap();
+
+    let error = client.chat(request).await.unwrap_err();
+    assert!(
+        matches!(error, LlmError::CassetteMismatch { .. }),
+        "{error}"
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
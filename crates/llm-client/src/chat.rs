@@ -0,0 +1,500 @@
+use crate::{LengthPolicy, Priority, Tool, ToolCall, ToolChoice};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Chat participant role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Role {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "assistant")]
+    Assistant,
+    #[serde(rename = "system")]
+    System,
+    /// Result of a tool call.
+    #[serde(rename = "tool")]
+    Tool,
+}
+
+/// Image sent to vision models: a link or the image itself embedded as a data URL.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+impl ImageUrl {
+    pub fn new(url: impl ToString) -> Self {
+        Self {
+            url: url.to_string(),
+        }
+    }
+
+    /// Embed the image data with the given MIME type (e.g. `image/jpeg`).
+    pub fn from_bytes(mime_type: &str, data: &[u8]) -> Self {
+        Self::new(format!("data:{mime_type};base64,{}", BASE64.encode(data)))
+    }
+}
+
+impl std::fmt::Debug for ImageUrl {
+    // embedded images would flood logs of requests
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.url.split_once(";base64,") {
+            Some((mime_type, data)) => write!(f, "{mime_type};base64,<{} chars>", data.len()),
+            None => write!(f, "{}", self.url),
+        }
+    }
+}
+
+/// Part of the content of a message with images, in the format of the providers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+/// Content of a message as sent to providers: a plain string, or parts if it has images.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+/// Chat message, in the format accepted by all supported providers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "WireMessage", into = "WireMessage")]
+pub struct ChatMessage {
+    pub role: Role,
+    /// Message text. Assistant messages with tool calls may have no content.
+    pub content: String,
+    /// Images sent after the text, for vision models (user messages only).
+    pub images: Vec<ImageUrl>,
+    /// Tools the model called (assistant messages only).
+    pub tool_calls: Vec<ToolCall>,
+    /// Id of the call this message is the result of (tool messages only).
+    pub tool_call_id: Option<String>,
+    /// Name of the called function (tool messages only).
+    pub name: Option<String>,
+    /// The model continues this message instead of starting a new one
+    /// (last assistant message only, Mistral).
+    pub prefix: bool,
+}
+
+/// [`ChatMessage`] with the text and images combined into the content.
+/// Serialization of messages is defined by this type, optional fields are omitted when empty.
+#[derive(Serialize, Deserialize)]
+struct WireMessage {
+    role: Role,
+    #[serde(default, deserialize_with = "null_as_default")]
+    content: MessageContent,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    prefix: bool,
+}
+
+impl From<ChatMessage> for WireMessage {
+    fn from(message: ChatMessage) -> Self {
+        let content = if message.images.is_empty() {
+            MessageContent::Text(message.content)
+        } else {
+            let text = Some(message.content)
+                .filter(|text| !text.is_empty())
+                .map(|text| ContentPart::Text { text });
+            let images = message
+                .images
+                .into_iter()
+                .map(|image_url| ContentPart::ImageUrl { image_url });
+            MessageContent::Parts(text.into_iter().chain(images).collect())
+        };
+
+        Self {
+            role: message.role,
+            content,
+            tool_calls: message.tool_calls,
+            tool_call_id: message.tool_call_id,
+            name: message.name,
+            prefix: message.prefix,
+        }
+    }
+}
+
+impl From<WireMessage> for ChatMessage {
+    fn from(message: WireMessage) -> Self {
+        let (content, images) = match message.content {
+            MessageContent::Text(text) => (text, Vec::new()),
+            MessageContent::Parts(parts) => {
+                let mut texts = Vec::new();
+                let mut images = Vec::new();
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => texts.push(text),
+                        ContentPart::ImageUrl { image_url } => images.push(image_url),
+                    }
+                }
+                (texts.join("\n"), images)
+            }
+        };
+
+        Self {
+            role: message.role,
+            content,
+            images,
+            tool_calls: message.tool_calls,
+            tool_call_id: message.tool_call_id,
+            name: message.name,
+            prefix: message.prefix,
+        }
+    }
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl ToString) -> Self {
+        Self {
+            role,
+            content: content.to_string(),
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            name: None,
+            prefix: false,
+        }
+    }
+
+    pub fn user(content: impl ToString) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: impl ToString) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    pub fn system(content: impl ToString) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    /// Attach an image to the message.
+    pub fn with_image(mut self, image: ImageUrl) -> Self {
+        self.images.push(image);
+        self
+    }
+
+    /// Beginning of the assistant reply the model has to continue.
+    pub fn assistant_prefix(content: impl ToString) -> Self {
+        Self {
+            prefix: true,
+            ..Self::assistant(content)
+        }
+    }
+
+    /// Result of the given tool call.
+    pub fn tool(call: &ToolCall, content: impl ToString) -> Self {
+        Self {
+            tool_call_id: Some(call.id.clone()),
+            name: Some(call.function.name.clone()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+}
+
+impl From<(Role, &str)> for ChatMessage {
+    fn from((role, content): (Role, &str)) -> Self {
+        Self::new(role, content)
+    }
+}
+
+pub trait ToHistory {
+    fn to_history(self) -> Vec<ChatMessage>;
+}
+
+impl ToHistory for Vec<ChatMessage> {
+    fn to_history(self) -> Vec<ChatMessage> {
+        self
+    }
+}
+
+impl<T> ToHistory for &[T]
+where
+    T: Into<ChatMessage> + Clone,
+{
+    fn to_history(self) -> Vec<ChatMessage> {
+        self.iter().map(|item| item.clone().into()).collect()
+    }
+}
+
+/// Replace the system message at the beginning of the history or insert a new one.
+pub(crate) fn set_system_message(history: &mut Vec<ChatMessage>, message: String) {
+    match history.first_mut() {
+        Some(ChatMessage {
+            role: Role::System,
+            content,
+            ..
+        }) => {
+            *content = message;
+        }
+        _ => {
+            history.insert(0, ChatMessage::system(message));
+        }
+    };
+}
+
+/// Format the model must use for its reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Reply must be a valid JSON object.
+    JsonObject,
+}
+
+/// Provider-neutral chat completion request.
+///
+/// Parameters that are not set fall back to the defaults configured on the client.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub messages: Vec<ChatMessage>,
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<usize>,
+    pub random_seed: Option<i64>,
+    pub response_format: Option<ResponseFormat>,
+    /// Only tokens in the top `top_p` probability mass are sampled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// Generation stops before any of these sequences.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// Penalty for tokens that already appeared in the text, encourages new topics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    /// Penalty for tokens proportional to how often they appeared, discourages repetition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    /// Number of completions to generate, see [`crate::Completion::choices`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<usize>,
+    /// Prepend the safety prompt of the provider (Mistral only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safe_prompt: Option<bool>,
+    pub tools: Vec<Tool>,
+    pub tool_choice: Option<ToolChoice>,
+    /// Skip response caches for this request, see [`crate::CachingClient`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bypass_cache: bool,
+    /// Order of the request in rate limiter queues, see [`crate::RateLimiter`].
+    #[serde(skip)]
+    pub priority: Priority,
+    /// What to do with a reply cut by the token limit, see [`crate::LlmClientExt::complete`].
+    #[serde(skip)]
+    pub length_policy: LengthPolicy,
+}
+
+impl ChatRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the model name.
+    pub fn with_model(mut self, model: impl ToString) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    /// Set the temperature of the model.
+    pub fn with_temperature(mut self, temperature: impl Into<f64>) -> Self {
+        self.temperature = Some(temperature.into());
+        self
+    }
+
+    /// Set the maximum number of tokens to generate.
+    pub fn with_max_tokens(mut self, max_tokens: impl Into<Option<usize>>) -> Self {
+        self.max_tokens = match max_tokens.into() {
+            Some(0) => None,
+            other => other,
+        };
+        self
+    }
+
+    /// Set the random seed for the model.
+    pub fn with_random_seed(mut self, random_seed: impl Into<Option<i64>>) -> Self {
+        self.random_seed = random_seed.into();
+        self
+    }
+
+    /// Set the format of the reply. Default is plain text.
+    pub fn with_response_format(
+        mut self,
+        response_format: impl Into<Option<ResponseFormat>>,
+    ) -> Self {
+        self.response_format = response_format.into();
+        self
+    }
+
+    /// Set the nucleus sampling probability mass. Default is the provider's default.
+    pub fn with_top_p(mut self, top_p: impl Into<Option<f64>>) -> Self {
+        self.top_p = top_p.into();
+        self
+    }
+
+    /// Add a sequence that stops the generation. The sequence is not included in the reply.
+    pub fn with_stop(mut self, stop: impl ToString) -> Self {
+        self.stop.push(stop.to_string());
+        self
+    }
+
+    /// Set the presence penalty, usually between -2 and 2. Default is 0.
+    pub fn with_presence_penalty(mut self, penalty: impl Into<Option<f64>>) -> Self {
+        self.presence_penalty = penalty.into();
+        self
+    }
+
+    /// Set the frequency penalty, usually between -2 and 2. Default is 0.
+    pub fn with_frequency_penalty(mut self, penalty: impl Into<Option<f64>>) -> Self {
+        self.frequency_penalty = penalty.into();
+        self
+    }
+
+    /// Set the number of completions to generate. Default is 1.
+    pub fn with_n(mut self, n: impl Into<Option<usize>>) -> Self {
+        self.n = n.into();
+        self
+    }
+
+    /// Set whether the safety prompt is prepended (Mistral only). Default is no.
+    pub fn with_safe_prompt(mut self, safe_prompt: impl Into<Option<bool>>) -> Self {
+        self.safe_prompt = safe_prompt.into();
+        self
+    }
+
+    /// Adds the beginning of the reply, the model continues it.
+    /// Mistral returns the reply including the prefix.
+    pub fn with_assistant_prefix(self, prefix: impl ToString) -> Self {
+        self.with_message(ChatMessage::assistant_prefix(prefix))
+    }
+
+    /// Always send the request to the provider, even if the response is cached.
+    pub fn with_cache_bypass(mut self, bypass_cache: bool) -> Self {
+        self.bypass_cache = bypass_cache;
+        self
+    }
+
+    /// Set the priority of the request. Default is [`Priority::Interactive`].
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set what to do with a reply cut by the token limit. Default is [`LengthPolicy::Fail`].
+    pub fn with_length_policy(mut self, length_policy: LengthPolicy) -> Self {
+        self.length_policy = length_policy;
+        self
+    }
+
+    /// Offer a tool to the model.
+    pub fn with_tool(mut self, tool: Tool) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    /// Set tools offered to the model.
+    /// Existing tools are replaced.
+    pub fn with_tools(mut self, tools: impl IntoIterator<Item = Tool>) -> Self {
+        self.tools = tools.into_iter().collect();
+        self
+    }
+
+    /// Set whether the model should call tools. Default is decided by the provider.
+    pub fn with_tool_choice(mut self, tool_choice: impl Into<Option<ToolChoice>>) -> Self {
+        self.tool_choice = tool_choice.into();
+        self
+    }
+
+    /// Adds a message to the end of the request.
+    pub fn with_message(mut self, message: impl Into<ChatMessage>) -> Self {
+        self.messages.push(message.into());
+        self
+    }
+
+    /// Adds a user message to the end of the request.
+    pub fn with_user_message(self, message: impl ToString) -> Self {
+        self.with_message(ChatMessage::user(message))
+    }
+
+    /// Adds an assistant message to the end of the request.
+    pub fn with_assistant_message(self, message: impl ToString) -> Self {
+        self.with_message(ChatMessage::assistant(message))
+    }
+
+    /// Set messages of the request.
+    /// Existing messages are replaced (including system messages).
+    pub fn with_history(mut self, history: impl ToHistory) -> Self {
+        self.messages = history.to_history();
+        self
+    }
+
+    /// Inserts system message to the beginning of the request.
+    /// If there is already a system message, it will be replaced.
+    pub fn with_system_message(mut self, message: impl ToString) -> Self {
+        set_system_message(&mut self.messages, message.to_string());
+        self
+    }
+}
+
+#[test]
+fn test_request_system_message() {
+    let request = ChatRequest::new()
+        .with_history(&[(Role::User, "hi"), (Role::Assistant, "hello")][..])
+        .with_system_message("first")
+        .with_system_message("second");
+
+    assert_eq!(
+        request.messages,
+        vec![
+            ChatMessage::system("second"),
+            ChatMessage::user("hi"),
+            ChatMessage::assistant("hello"),
+        ]
+    );
+}
+
+#[test]
+fn test_message_with_images() {
+    let message =
+        ChatMessage::user("What is it?").with_image(ImageUrl::from_bytes("image/png", b"png"));
+
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(
+        json["content"],
+        serde_json::json!([
+            {"type": "text", "text": "What is it?"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,cG5n"}},
+        ])
+    );
+    assert_eq!(
+        serde_json::from_value::<ChatMessage>(json).unwrap(),
+        message
+    );
+
+    // messages without images are sent as plain strings
+    let json = serde_json::to_value(ChatMessage::user("Hi")).unwrap();
+    assert_eq!(json["content"], "Hi");
+}
@@ -0,0 +1,192 @@
+//! Wire format of the `/chat/completions` endpoint shared by Mistral and OpenAI compatible APIs.
+
+use crate::{
+    error_for_status,
+    sse::{SseParser, SSE_DONE},
+    ChatMessage, ChatRequest, Completion, ContentStream, FinishReason, LengthPolicy, LlmError,
+    LlmResult, RetryPolicy, StreamEvent, Usage,
+};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use reqwest::header::{HeaderValue, ACCEPT};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Response {
+    #[serde(default)]
+    id: Option<String>,
+    model: String,
+    choices: Vec<ResponseChoice>,
+    usage: Usage,
+}
+
+impl Response {
+    /// Take the messages of the choices, the first one is the main message.
+    /// Fails if there are no choices.
+    pub fn into_completion(self) -> LlmResult<Completion> {
+        let mut choices = self.choices;
+        choices.sort_by_key(|choice| choice.index);
+
+        let first = choices.first().ok_or(LlmError::EmptyChoices)?;
+        let message = first.message.clone();
+        let finish_reason = first.finish_reason;
+
+        let choices = match choices.len() {
+            1 => Vec::new(),
+            _ => choices
+                .into_iter()
+                .filter(|choice| !choice.finish_reason.is_truncated())
+                .map(|choice| choice.message)
+                .collect(),
+        };
+
+        Ok(Completion {
+            id: self.id,
+            message,
+            choices,
+            finish_reason,
+            usage: self.usage,
+            model: self.model,
+        })
+    }
+}
+
+/// Add optional sampling parameters of the request to the body, only the ones that are set.
+pub(crate) fn insert_sampling_params(body: &mut Value, request: &ChatRequest) {
+    if let Some(top_p) = request.top_p {
+        body["top_p"] = top_p.into();
+    }
+    if !request.stop.is_empty() {
+        body["stop"] = json!(request.stop);
+    }
+    if let Some(penalty) = request.presence_penalty {
+        body["presence_penalty"] = penalty.into();
+    }
+    if let Some(penalty) = request.frequency_penalty {
+        body["frequency_penalty"] = penalty.into();
+    }
+    if let Some(n) = request.n {
+        body["n"] = n.into();
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ResponseChoice {
+    #[serde(default)]
+    index: usize,
+    message: ChatMessage,
+    finish_reason: FinishReason,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+    /// Sent with the last chunk.
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    index: usize,
+    delta: StreamDelta,
+    #[serde(default)]
+    finish_reason: Option<FinishReason>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StreamDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// Send a completion request, retrying it according to the policy.
+/// `name` identifies the provider in logs.
+pub(crate) async fn send(
+    request: impl Fn() -> reqwest::RequestBuilder,
+    retry_policy: &RetryPolicy,
+    name: &str,
+) -> LlmResult<Response> {
+    let str_resp = retry_policy
+        .run(&format!("{name} request"), || async {
+            let response = request()
+                .header(ACCEPT, HeaderValue::from_static("application/json"))
+                .send()
+                .await?;
+            let response = error_for_status(response).await?;
+
+            LlmResult::Ok(response.text().await?)
+        })
+        .await?;
+
+    log::debug!("{name} response: {}", str_resp);
+
+    serde_json::from_str(&str_resp).map_err(|e| LlmError::malformed(e, &str_resp))
+}
+
+/// Send a streaming completion request (the body must have `"stream": true`).
+/// Only establishing the stream is retried, failures in the middle of the stream are returned
+/// as stream items. A reply cut by the token limit ends with [`StreamEvent::Truncated`]
+/// if the policy is [`LengthPolicy::Partial`] and fails otherwise.
+pub(crate) async fn stream(
+    request: impl Fn() -> reqwest::RequestBuilder,
+    retry_policy: &RetryPolicy,
+    length_policy: LengthPolicy,
+    name: &str,
+) -> LlmResult<ContentStream> {
+    let response = retry_policy
+        .run(&format!("{name} stream request"), || async {
+            let response = request()
+                .header(ACCEPT, HeaderValue::from_static("text/event-stream"))
+                .send()
+                .await?;
+            error_for_status(response).await
+        })
+        .await?;
+
+    let name = name.to_string();
+    let mut parser = SseParser::default();
+    let mut received = String::new();
+
+    let deltas = response
+        .bytes_stream()
+        .map_err(LlmError::from)
+        .map_ok(move |chunk| stream::iter(parser.push(&chunk).into_iter().map(LlmResult::Ok)))
+        .try_flatten()
+        .try_take_while(|data| future::ready(Ok(data != SSE_DONE)))
+        .map(move |data| {
+            let data = data?;
+            log::trace!("{name} stream chunk: {}", data);
+
+            let chunk: StreamChunk =
+                serde_json::from_str(&data).map_err(|e| LlmError::malformed(e, &data))?;
+
+            let mut content = String::new();
+            let mut truncated = false;
+            // only the first choice is streamed
+            for choice in chunk.choices.into_iter().filter(|choice| choice.index == 0) {
+                content.extend(choice.delta.content);
+                truncated |= choice.finish_reason.is_some_and(FinishReason::is_truncated);
+            }
+
+            received.push_str(&content);
+            if truncated && length_policy != LengthPolicy::Partial {
+                return Err(LlmError::Truncated {
+                    partial: received.clone(),
+                });
+            }
+
+            let events = (!content.is_empty())
+                .then_some(StreamEvent::Delta(content))
+                .into_iter()
+                .chain(truncated.then_some(StreamEvent::Truncated))
+                .chain(chunk.usage.map(StreamEvent::Usage))
+                .map(LlmResult::Ok);
+
+            Ok(stream::iter(events))
+        })
+        .try_flatten();
+
+    Ok(deltas.boxed())
+}
@@ -0,0 +1,84 @@
+use crate::{ChatMessage, LlmError, LlmResult, Usage};
+use serde::{Deserialize, Serialize};
+
+/// Why the model stopped generating.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// Model finished the reply or generated a stop sequence.
+    #[default]
+    Stop,
+    /// Reply reached `max_tokens`.
+    Length,
+    /// Reply reached the context window of the model (Mistral).
+    ModelLength,
+    /// Model called tools.
+    ToolCalls,
+    /// Reasons specific to some providers (e.g. `content_filter`).
+    #[serde(other)]
+    Other,
+}
+
+impl FinishReason {
+    /// Whether the reply was cut by a token limit.
+    pub fn is_truncated(self) -> bool {
+        matches!(self, Self::Length | Self::ModelLength)
+    }
+}
+
+/// Result of a chat completion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Completion {
+    /// Id of the response assigned by the provider, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// First choice of the model.
+    pub message: ChatMessage,
+    /// All choices, including the first one, if more than one was requested.
+    /// Truncated choices are skipped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<ChatMessage>,
+    /// Why the model stopped generating the first choice.
+    #[serde(default)]
+    pub finish_reason: FinishReason,
+    pub usage: Usage,
+    /// Model that generated the completion, as reported by the provider.
+    pub model: String,
+}
+
+impl Completion {
+    pub fn content(&self) -> &str {
+        &self.message.content
+    }
+
+    /// Whether the first choice was cut by a token limit.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason.is_truncated()
+    }
+
+    /// Fail with [`LlmError::Truncated`] if the first choice was cut by a token limit.
+    pub fn into_complete(self) -> LlmResult<Self> {
+        if self.is_truncated() {
+            return Err(LlmError::Truncated {
+                partial: self.message.content,
+            });
+        }
+
+        Ok(self)
+    }
+}
+
+/// What to do when the model stops because of the token limit,
+/// see [`crate::LlmClientExt::complete`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LengthPolicy {
+    /// Fail with [`LlmError::Truncated`].
+    #[default]
+    Fail,
+    /// Return the truncated completion, so the caller can use the partial reply.
+    Partial,
+    /// Send the request again with twice as many `max_tokens`, up to `max_retries` times,
+    /// then fail. Requests without `max_tokens` on a client without a default fail right away,
+    /// as do streams, since the streamed part of the reply can't be taken back.
+    Retry { max_retries: u32 },
+}
@@ -0,0 +1,324 @@
+use crate::{
+    estimate_tokens, ChatMessage, ChatRequest, LlmClient, LlmClientExt, LlmError, LlmResult,
+    Priority, Role,
+};
+use futures::future::try_join_all;
+
+/// Context window of models with an unknown limit.
+pub const DEFAULT_CONTEXT_WINDOW: u64 = 8192;
+
+/// Requests smaller than this fit the window of any supported model,
+/// so the limit of the model isn't looked up for them.
+const MIN_CONTEXT_WINDOW: u64 = 4096;
+
+/// Tokens reserved for the completion of a request without `max_tokens`.
+pub(crate) const DEFAULT_COMPLETION_TOKENS: u64 = 256;
+
+/// Part of the window that is filled, as token counts are only estimated.
+const CONTEXT_WINDOW_FILL: f64 = 0.9;
+
+/// Tokens of the role and the formatting around the content of a message.
+const MESSAGE_OVERHEAD_TOKENS: u64 = 4;
+
+/// Rough number of tokens an image takes, vision models scale images to about this size.
+const IMAGE_TOKENS: u64 = 1024;
+
+/// Replaces the removed part of a text truncated in the middle.
+const TRUNCATION_MARKER: &str = "\n[...]\n";
+
+/// How many times summaries are summarized again if they are still too long.
+const MAX_SUMMARY_ROUNDS: usize = 3;
+
+const SUMMARY_PROMPT: &str = "Summarize the text sent by the user. Keep all names, numbers, \
+dates and links, and write in the language of the text. Reply with the summary only.";
+
+/// Context windows of well-known models by model id prefix.
+const KNOWN_CONTEXT_WINDOWS: &[(&str, u64)] = &[
+    ("mistral-tiny", 32_000),
+    ("mistral-small", 32_000),
+    ("mistral-medium", 32_000),
+    ("mistral-large", 128_000),
+    ("open-mistral-7b", 32_000),
+    ("open-mistral-nemo", 128_000),
+    ("open-mixtral-8x7b", 32_000),
+    ("open-mixtral-8x22b", 64_000),
+    ("codestral", 32_000),
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-4o", 128_000),
+];
+
+/// What to do with a request that doesn't fit the context window of the model.
+///
+/// If the strategy isn't enough, the last message is truncated in the middle as well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TruncationStrategy {
+    /// Cut the middle of the last message, keeping its beginning and end.
+    #[default]
+    TruncateMiddle,
+    /// Remove the oldest messages after the system prompt, keeping the last message.
+    DropOldestHistory,
+    /// Replace the last message with summaries of its chunks made by the same model.
+    /// Summaries are sent with [`Priority::Background`], so a long message doesn't hold up
+    /// other requests in rate limiter queues.
+    SummarizeChunks,
+}
+
+/// Context window of the model if it's a well-known one.
+pub fn known_context_window(model: &str) -> Option<u64> {
+    KNOWN_CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
+
+/// Rough number of tokens the message takes in a prompt.
+pub fn estimate_message_tokens(message: &ChatMessage) -> u64 {
+    let tool_calls: u64 = message
+        .tool_calls
+        .iter()
+        .map(|call| {
+            estimate_tokens(&call.function.name) + estimate_tokens(&call.function.arguments)
+        })
+        .sum();
+
+    let images = message.images.len() as u64 * IMAGE_TOKENS;
+
+    estimate_tokens(&message.content) + images + tool_calls + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Rough number of tokens the messages take in a prompt.
+pub fn estimate_prompt_tokens(messages: &[ChatMessage]) -> u64 {
+    messages.iter().map(estimate_message_tokens).sum()
+}
+
+/// Tokens reserved for the completion of the request.
+pub(crate) fn completion_tokens(request: &ChatRequest) -> u64 {
+    request
+        .max_tokens
+        .map_or(DEFAULT_COMPLETION_TOKENS, |tokens| tokens as u64)
+}
+
+/// Cut the middle of the text so it takes about `max_tokens`.
+pub fn truncate_middle(text: &str, max_tokens: u64) -> String {
+    let max_chars = max_tokens as usize * 4;
+    let chars = text.chars().count();
+    if chars <= max_chars {
+        return text.to_string();
+    }
+
+    let kept = max_chars.saturating_sub(TRUNCATION_MARKER.len());
+    let tail = kept / 2;
+    let head = kept - tail;
+    let byte_at = |char_index: usize| {
+        text.char_indices()
+            .nth(char_index)
+            .map_or(text.len(), |(i, _)| i)
+    };
+
+    format!(
+        "{}{TRUNCATION_MARKER}{}",
+        &text[..byte_at(head)],
+        &text[byte_at(chars - tail)..]
+    )
+}
+
+/// Split the text into chunks of at most `max_tokens`,
+/// preferably at paragraph, line or word boundaries.
+pub(crate) fn split_text(text: &str, max_tokens: u64) -> Vec<&str> {
+    let max_chars = (max_tokens as usize * 4).max(1);
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while let Some((end, _)) = rest.char_indices().nth(max_chars) {
+        let middle = rest.char_indices().nth(max_chars / 2).map_or(0, |(i, _)| i);
+        let split = ["\n\n", "\n", " "]
+            .iter()
+            .find_map(|separator| {
+                let position = rest[middle..end].rfind(separator)?;
+                Some(middle + position + separator.len())
+            })
+            .unwrap_or(end);
+
+        chunks.push(&rest[..split]);
+        rest = &rest[split..];
+    }
+
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+
+    chunks
+}
+
+/// Remove the oldest messages after the system prompt until the prompt fits the budget.
+/// The last message is always kept and the history always starts with a user message.
+fn drop_oldest_history(messages: &mut Vec<ChatMessage>, budget: u64) {
+    let first = messages
+        .iter()
+        .position(|message| message.role != Role::System)
+        .unwrap_or(messages.len());
+    let has_history = |messages: &Vec<ChatMessage>| messages.len() > first + 1;
+
+    while has_history(messages) && estimate_prompt_tokens(messages) > budget {
+        messages.remove(first);
+        while has_history(messages) && messages[first].role != Role::User {
+            messages.remove(first);
+        }
+    }
+}
+
+/// Truncate the last message so the prompt fits the budget.
+fn truncate_last_message(messages: &mut [ChatMessage], budget: u64) -> LlmResult<()> {
+    let tokens = estimate_prompt_tokens(messages);
+    if tokens <= budget {
+        return Ok(());
+    }
+
+    let exceeded = LlmError::ContextWindowExceeded {
+        tokens,
+        limit: budget,
+    };
+    let Some((last, others)) = messages.split_last_mut() else {
+        return Err(exceeded);
+    };
+    let available = budget.saturating_sub(estimate_prompt_tokens(others) + MESSAGE_OVERHEAD_TOKENS);
+    if available == 0 {
+        return Err(exceeded);
+    }
+
+    last.content = truncate_middle(&last.content, available);
+
+    Ok(())
+}
+
+/// Replace the last message with summaries of its chunks until the prompt fits the budget.
+async fn summarize_last_message<C: LlmClient + ?Sized>(
+    client: &C,
+    request: &mut ChatRequest,
+    window: u64,
+    budget: u64,
+) -> LlmResult<()> {
+    for _ in 0..MAX_SUMMARY_ROUNDS {
+        let Some((last, others)) = request.messages.split_last() else {
+            return Ok(());
+        };
+        let available = budget.saturating_sub(estimate_prompt_tokens(others));
+        if estimate_message_tokens(last) <= available {
+            return Ok(());
+        }
+
+        // every chunk is sent with the summary prompt and gets a share of the available tokens
+        let chunks = split_text(&last.content, window / 2);
+        let summary_tokens = (available / chunks.len() as u64).min(window / 4).max(16);
+        log::info!(
+            "Summarizing {} chunks of a long message to {summary_tokens} tokens each",
+            chunks.len()
+        );
+
+        let summaries = chunks.into_iter().map(|chunk| {
+            let summary_request = ChatRequest {
+                messages: Vec::new(),
+                model: request.model.clone(),
+                temperature: request.temperature,
+                random_seed: request.random_seed,
+                priority: Priority::Background,
+                ..Default::default()
+            }
+            .with_max_tokens(summary_tokens as usize)
+            .with_system_message(SUMMARY_PROMPT)
+            .with_user_message(chunk);
+
+            async move {
+                // a cut summary is still shorter than the chunk
+                let completion = client.chat(summary_request).await?;
+                LlmResult::Ok(completion.message.content)
+            }
+        });
+        let summary = try_join_all(summaries).await?.join("\n\n");
+
+        if let Some(last) = request.messages.last_mut() {
+            last.content = summary;
+        }
+    }
+
+    Ok(())
+}
+
+/// Make the request fit the context window, see [`crate::LlmClientExt::fit_context`].
+pub(crate) async fn fit_context<C: LlmClient + ?Sized>(
+    client: &C,
+    mut request: ChatRequest,
+    strategy: TruncationStrategy,
+) -> LlmResult<ChatRequest> {
+    let completion = completion_tokens(&request);
+    let tokens = estimate_prompt_tokens(&request.messages);
+    if tokens + completion <= MIN_CONTEXT_WINDOW {
+        return Ok(request);
+    }
+
+    let window = client.context_window(request.model.as_deref()).await;
+    let budget = ((window as f64 * CONTEXT_WINDOW_FILL) as u64).saturating_sub(completion);
+    if tokens <= budget {
+        return Ok(request);
+    }
+
+    log::info!(
+        "Request takes about {tokens} tokens, only {budget} fit the context window, \
+        applying {strategy:?}"
+    );
+
+    match strategy {
+        TruncationStrategy::TruncateMiddle => {}
+        TruncationStrategy::DropOldestHistory => drop_oldest_history(&mut request.messages, budget),
+        TruncationStrategy::SummarizeChunks => {
+            summarize_last_message(client, &mut request, window, budget).await?
+        }
+    }
+    truncate_last_message(&mut request.messages, budget)?;
+
+    Ok(request)
+}
+
+#[test]
+fn test_truncate_middle() {
+    let text = "a".repeat(100) + &"b".repeat(100);
+
+    let truncated = truncate_middle(&text, 10);
+    assert_eq!(truncated.chars().count(), 40);
+    assert!(truncated.starts_with("aaaa") && truncated.ends_with("bbbb"));
+    assert!(truncated.contains(TRUNCATION_MARKER));
+
+    assert_eq!(truncate_middle("short", 10), "short");
+    assert_eq!(truncate_middle("привет мир", 2).chars().count(), 8);
+}
+
+#[test]
+fn test_split_text() {
+    let text = "first paragraph\n\nsecond one and more words";
+    let chunks = split_text(text, 5);
+
+    assert_eq!(chunks.concat(), text);
+    assert_eq!(chunks[0], "first paragraph\n\n");
+    assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 20));
+}
+
+#[test]
+fn test_drop_oldest_history() {
+    let long = "x".repeat(400);
+    let mut messages = vec![
+        ChatMessage::system("prompt"),
+        ChatMessage::user(&long),
+        ChatMessage::assistant(&long),
+        ChatMessage::user("question"),
+        ChatMessage::assistant("answer"),
+        ChatMessage::user("last"),
+    ];
+
+    drop_oldest_history(&mut messages, 50);
+
+    let contents = messages
+        .iter()
+        .map(|m| m.content.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(contents, ["prompt", "question", "answer", "last"]);
+}
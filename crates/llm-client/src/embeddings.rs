@@ -0,0 +1,174 @@
+use crate::{error_for_status, LlmError, LlmResult, RetryPolicy, Usage};
+use reqwest::header::{HeaderValue, ACCEPT};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Maximum number of texts sent in a single embeddings request.
+pub const MAX_EMBEDDING_BATCH_SIZE: usize = 64;
+
+/// Maximum total length (in chars) of texts sent in a single embeddings request.
+/// Roughly matches the 16k tokens per request limit of `mistral-embed`.
+pub const MAX_EMBEDDING_BATCH_CHARS: usize = 32_000;
+
+/// Client able to compute text embeddings.
+#[async_trait::async_trait]
+pub trait EmbeddingClient: Send + Sync {
+    /// Compute embeddings of the texts, one vector per text in the same order.
+    /// Large inputs are split into several requests.
+    async fn embed(&self, texts: &[String]) -> LlmResult<Embeddings>;
+
+    /// Model used to compute embeddings.
+    fn embedding_model(&self) -> &str;
+}
+
+/// Result of an embeddings call.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Embeddings {
+    pub vectors: Vec<Vec<f32>>,
+    /// Tokens used by all requests of the call.
+    pub usage: Usage,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Response {
+    data: Vec<ResponseItem>,
+    usage: Usage,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ResponseItem {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Cosine similarity of two vectors, `0.0` if one of them is zero.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm_a = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+/// Split texts into batches that fit [`MAX_EMBEDDING_BATCH_SIZE`] and
+/// [`MAX_EMBEDDING_BATCH_CHARS`]. A text exceeding the chars limit gets a batch of its own.
+fn split_batches(texts: &[String]) -> Vec<&[String]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut chars = 0;
+
+    for (i, text) in texts.iter().enumerate() {
+        let len = text.chars().count();
+        let is_full =
+            i - start >= MAX_EMBEDDING_BATCH_SIZE || chars + len > MAX_EMBEDDING_BATCH_CHARS;
+
+        if i > start && is_full {
+            batches.push(&texts[start..i]);
+            start = i;
+            chars = 0;
+        }
+        chars += len;
+    }
+
+    if start < texts.len() {
+        batches.push(&texts[start..]);
+    }
+
+    batches
+}
+
+/// Send texts to the `{api_url}/embeddings` endpoint in batches.
+/// `name` identifies the provider in logs.
+pub(crate) async fn embed(
+    client: &reqwest::Client,
+    api_url: &str,
+    api_key: Option<&str>,
+    model: &str,
+    texts: &[String],
+    retry_policy: &RetryPolicy,
+    name: &str,
+) -> LlmResult<Embeddings> {
+    let url = format!("{api_url}/embeddings");
+    let mut result = Embeddings::default();
+
+    for batch in split_batches(texts) {
+        let body = json!({
+            "model": model,
+            "input": batch,
+            "encoding_format": "float",
+        });
+
+        let str_resp = retry_policy
+            .run(&format!("{name} embeddings request"), || async {
+                let mut request = client
+                    .post(&url)
+                    .header(ACCEPT, HeaderValue::from_static("application/json"))
+                    .json(&body);
+                if let Some(api_key) = api_key {
+                    request = request.bearer_auth(api_key);
+                }
+
+                let response = error_for_status(request.send().await?).await?;
+
+                LlmResult::Ok(response.text().await?)
+            })
+            .await?;
+
+        let Response { mut data, usage } =
+            serde_json::from_str(&str_resp).map_err(|e| LlmError::malformed(e, &str_resp))?;
+
+        if data.len() != batch.len() {
+            return Err(LlmError::UnexpectedEmbeddingsCount {
+                expected: batch.len(),
+                actual: data.len(),
+            });
+        }
+
+        data.sort_by_key(|item| item.index);
+        result
+            .vectors
+            .extend(data.into_iter().map(|item| item.embedding));
+        result.usage += usage;
+    }
+
+    Ok(result)
+}
+
+#[test]
+fn test_split_batches() {
+    let texts = vec!["a".repeat(10); MAX_EMBEDDING_BATCH_SIZE * 2 + 1];
+    let sizes = split_batches(&texts)
+        .iter()
+        .map(|batch| batch.len())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sizes,
+        [MAX_EMBEDDING_BATCH_SIZE, MAX_EMBEDDING_BATCH_SIZE, 1]
+    );
+
+    let long = "a".repeat(MAX_EMBEDDING_BATCH_CHARS);
+    let texts = vec![
+        "short".to_string(),
+        long,
+        "short".to_string(),
+        "short".to_string(),
+    ];
+    let sizes = split_batches(&texts)
+        .iter()
+        .map(|batch| batch.len())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [1, 1, 2]);
+
+    assert!(split_batches(&[]).is_empty());
+}
+
+#[test]
+fn test_cosine_similarity() {
+    assert_eq!(cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]), 1.0);
+    assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+    assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+}
@@ -0,0 +1,220 @@
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use std::{path::PathBuf, time::Duration};
+
+pub type LlmResult<T> = Result<T, LlmError>;
+
+/// Error returned by LLM clients.
+#[derive(Debug, thiserror::Error)]
+pub enum LlmError {
+    /// API key is missing, invalid or has no access to the requested resource.
+    #[error("authentication failed ({status}): {body}")]
+    Unauthorized { status: StatusCode, body: String },
+
+    /// Provider rejected the request because of the rate limit (HTTP 429).
+    #[error("rate limit exceeded: {body}")]
+    RateLimited {
+        body: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// Provider failed to process the request (HTTP 5xx).
+    #[error("server error ({status}): {body}")]
+    Server {
+        status: StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// Any other unsuccessful response, usually caused by an invalid request.
+    #[error("unexpected response status ({status}): {body}")]
+    UnexpectedStatus { status: StatusCode, body: String },
+
+    /// Failed to send the request or to receive the response.
+    #[error("transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// Response body doesn't match the expected format.
+    #[error("malformed response: {source}")]
+    MalformedResponse {
+        source: serde_json::Error,
+        body: String,
+    },
+
+    /// Model reply doesn't match the requested structure, even after re-asking.
+    #[error("model reply doesn't match the expected format: {source}")]
+    InvalidStructuredOutput {
+        source: serde_json::Error,
+        content: String,
+    },
+
+    /// Model kept calling tools without giving the final reply.
+    #[error("model didn't finish calling tools in {rounds} rounds")]
+    ToolRoundsExceeded { rounds: usize },
+
+    /// Embeddings response contains a different number of vectors than texts sent.
+    #[error("expected {expected} embeddings, got {actual}")]
+    UnexpectedEmbeddingsCount { expected: usize, actual: usize },
+
+    /// Response doesn't contain any choices.
+    #[error("response contains no choices")]
+    EmptyChoices,
+
+    /// Moderation response doesn't contain a result for the text.
+    #[error("moderation response contains no results")]
+    EmptyModerationResults,
+
+    /// Model stopped because it reached the token limit.
+    /// Contains the content generated before the limit was reached.
+    #[error("response was truncated because of the token limit")]
+    Truncated { partial: String },
+
+    /// Request doesn't fit the context window of the model, even after truncation.
+    #[error("request takes about {tokens} tokens, but only {limit} fit the context window")]
+    ContextWindowExceeded { tokens: u64, limit: u64 },
+
+    /// Configured model is not available for the API key.
+    #[error("model {model:?} is not available, available models: {available:?}")]
+    UnknownModel {
+        model: String,
+        available: Vec<String>,
+    },
+
+    /// Request (including retries) didn't complete in time.
+    #[error("deadline exceeded after {attempts} attempt(s)")]
+    DeadlineExceeded { attempts: u32 },
+
+    /// Replayed request was never recorded, see [`crate::CassetteClient`].
+    #[error("request is not recorded, expected cassette {path:?}")]
+    UnrecordedRequest { path: PathBuf },
+
+    /// Recorded response is of another kind than the replayed call (streamed or not).
+    #[error("cassette {path:?} doesn't contain a {expected} response")]
+    CassetteMismatch {
+        path: PathBuf,
+        expected: &'static str,
+    },
+
+    /// Failed to read or write a cassette file.
+    #[error("failed to access cassette {path:?}: {source}")]
+    CassetteIo {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl LlmError {
+    /// Create an error from an unsuccessful response status and its body.
+    /// `retry_after` is the delay requested by the provider, if any.
+    pub fn from_status(status: StatusCode, body: String, retry_after: Option<Duration>) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Unauthorized { status, body },
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited { body, retry_after },
+            status if status.is_server_error() => Self::Server {
+                status,
+                body,
+                retry_after,
+            },
+            status => Self::UnexpectedStatus { status, body },
+        }
+    }
+
+    /// Whether the same request may succeed if it is sent again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } | Self::Server { .. } => true,
+            Self::Transport(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            _ => false,
+        }
+    }
+
+    /// Whether the error means that the provider is unavailable,
+    /// so the request may succeed on another provider.
+    pub fn is_provider_failure(&self) -> bool {
+        self.is_transient()
+            || matches!(
+                self,
+                Self::Unauthorized { .. }
+                    | Self::DeadlineExceeded { .. }
+                    | Self::MalformedResponse { .. }
+                    | Self::EmptyChoices
+            )
+    }
+
+    /// Delay requested by the provider before the request is retried.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after, .. } | Self::Server { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn malformed(source: serde_json::Error, body: impl ToString) -> Self {
+        Self::MalformedResponse {
+            source,
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Turn an unsuccessful response into an error.
+pub(crate) async fn error_for_status(response: reqwest::Response) -> LlmResult<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+
+    let body = response.text().await.unwrap_or_default();
+    log::debug!("Unsuccessful response ({status}): {body}");
+
+    Err(LlmError::from_status(status, body, retry_after))
+}
+
+/// Parse `Retry-After` header value: either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+
+    // date in the past means the request can be retried right away
+    Some(delay.to_std().unwrap_or_default())
+}
+
+#[test]
+fn test_error_from_status() {
+    let error =
+        |status: u16| LlmError::from_status(StatusCode::from_u16(status).unwrap(), "".into(), None);
+
+    assert!(matches!(error(401), LlmError::Unauthorized { .. }));
+    assert!(matches!(error(403), LlmError::Unauthorized { .. }));
+    assert!(matches!(error(429), LlmError::RateLimited { .. }));
+    assert!(matches!(error(503), LlmError::Server { .. }));
+    assert!(matches!(error(400), LlmError::UnexpectedStatus { .. }));
+}
+
+#[test]
+fn test_parse_retry_after() {
+    assert_eq!(parse_retry_after("3"), Some(Duration::from_secs(3)));
+    assert_eq!(
+        parse_retry_after(" 1.5 "),
+        Some(Duration::from_millis(1500))
+    );
+    assert_eq!(parse_retry_after("-1"), None);
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon"), None);
+}
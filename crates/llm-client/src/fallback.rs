@@ -0,0 +1,320 @@
+use crate::{ChatRequest, Completion, ContentStream, LlmClient, LlmResult, ModelInfo};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Describes when a provider is considered down and for how long it is skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreakerPolicy {
+    failure_threshold: u32,
+    open_duration: Duration,
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl CircuitBreakerPolicy {
+    /// Set the number of consecutive failures after which the provider is skipped. Default is 3.
+    pub fn with_failure_threshold(mut self, failure_threshold: u32) -> Self {
+        self.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// Set how long the provider is skipped before it is tried again. Default is 30s.
+    pub fn with_open_duration(mut self, open_duration: Duration) -> Self {
+        self.open_duration = open_duration;
+        self
+    }
+}
+
+/// State of the circuit breaker of a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Provider is healthy and receives requests.
+    Closed,
+    /// Provider failed too many times and is skipped.
+    Open,
+    /// Provider was skipped long enough, a single request checks whether it has recovered.
+    HalfOpen,
+}
+
+/// Health of a provider as seen by [`FallbackClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderHealth {
+    pub name: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// A request checking whether the half-open provider has recovered is in flight.
+    probing: bool,
+}
+
+impl Breaker {
+    fn state(&self) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(until) if Instant::now() < until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    fn on_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.open_until = None;
+    }
+
+    fn on_failure(&mut self, policy: &CircuitBreakerPolicy) {
+        self.consecutive_failures += 1;
+
+        // a failed check of a recovering provider opens the circuit again right away
+        let is_half_open = self.state() == CircuitState::HalfOpen;
+        if is_half_open || self.consecutive_failures >= policy.failure_threshold {
+            self.open_until = Some(Instant::now() + policy.open_duration);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Provider {
+    name: String,
+    client: Arc<dyn LlmClient>,
+    breaker: Mutex<Breaker>,
+}
+
+impl Provider {
+    fn new(name: impl ToString, client: Arc<dyn LlmClient>) -> Self {
+        Self {
+            name: name.to_string(),
+            client,
+            breaker: Default::default(),
+        }
+    }
+
+    fn breaker(&self) -> std::sync::MutexGuard<'_, Breaker> {
+        self.breaker.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Check whether a request can be sent to the provider. Only one request at a time is let
+    /// through to a half-open provider, the others go to the next provider.
+    fn admit(&self) -> Option<Admission<'_>> {
+        let mut breaker = self.breaker();
+
+        let probe = match breaker.state() {
+            CircuitState::Closed => false,
+            CircuitState::Open => return None,
+            CircuitState::HalfOpen if breaker.probing => return None,
+            CircuitState::HalfOpen => {
+                breaker.probing = true;
+                true
+            }
+        };
+
+        Some(Admission {
+            provider: self,
+            probe,
+        })
+    }
+}
+
+/// Permission to send a request to a provider.
+/// Releases the probe of a half-open provider when dropped, even if the request was cancelled.
+struct Admission<'a> {
+    provider: &'a Provider,
+    probe: bool,
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            self.provider.breaker().probing = false;
+        }
+    }
+}
+
+/// Client that sends requests to the first healthy provider of an ordered list.
+///
+/// Every provider has a circuit breaker: after several consecutive failures the provider is
+/// skipped for a while, then a single request checks whether it has recovered while concurrent
+/// requests go to the next providers. Providers are always tried in order, so the traffic goes
+/// back to the preferred provider once it's healthy.
+///
+/// The model set in a request is only sent to the first provider, the others use their
+/// default models, as model names are usually specific to a provider.
+#[derive(Debug)]
+pub struct FallbackClient {
+    /// Never empty, the first provider is given to the constructor.
+    providers: Vec<Provider>,
+    policy: CircuitBreakerPolicy,
+}
+
+impl FallbackClient {
+    /// Create a client with the preferred provider. `name` identifies the provider in logs.
+    pub fn new(name: impl ToString, client: Arc<dyn LlmClient>) -> Self {
+        Self {
+            providers: vec![Provider::new(name, client)],
+            policy: Default::default(),
+        }
+    }
+
+    /// Add a provider to the end of the list. `name` identifies the provider in logs.
+    pub fn with_provider(mut self, name: impl ToString, client: Arc<dyn LlmClient>) -> Self {
+        self.providers.push(Provider::new(name, client));
+        self
+    }
+
+    /// Set the circuit breaker policy of all providers.
+    /// Default is [`CircuitBreakerPolicy::default`].
+    pub fn with_circuit_breaker(mut self, policy: CircuitBreakerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Current health of the providers, in order.
+    pub fn health(&self) -> Vec<ProviderHealth> {
+        self.providers
+            .iter()
+            .map(|provider| {
+                let breaker = provider.breaker();
+                ProviderHealth {
+                    name: provider.name.clone(),
+                    state: breaker.state(),
+                    consecutive_failures: breaker.consecutive_failures,
+                }
+            })
+            .collect()
+    }
+
+    fn primary(&self) -> &Provider {
+        &self.providers[0]
+    }
+
+    /// Run the call on the providers in order until one of them succeeds.
+    /// If all providers are down, all of them are tried anyway.
+    async fn run<T, F, Fut>(&self, name: &str, mut call: F) -> LlmResult<T>
+    where
+        F: FnMut(usize, Arc<dyn LlmClient>) -> Fut,
+        Fut: Future<Output = LlmResult<T>>,
+    {
+        let mut last_error = None;
+        let mut skipped = Vec::new();
+
+        for (index, provider) in self.providers.iter().enumerate() {
+            let Some(_admission) = provider.admit() else {
+                skipped.push((index, provider));
+                continue;
+            };
+
+            match self.call_provider(name, index, provider, &mut call).await {
+                Err(error) if error.is_provider_failure() => last_error = Some(error),
+                result => return result,
+            }
+        }
+
+        if let Some(error) = last_error {
+            return Err(error);
+        }
+
+        log::warn!("All LLM providers are down, trying all of them");
+        for (index, provider) in skipped {
+            match self.call_provider(name, index, provider, &mut call).await {
+                Err(error) if error.is_provider_failure() => last_error = Some(error),
+                result => return result,
+            }
+        }
+
+        // every provider was tried, and there is at least one
+        Err(last_error.expect("all providers failed"))
+    }
+
+    /// Run the call on a single provider and update its circuit breaker.
+    async fn call_provider<T, F, Fut>(
+        &self,
+        name: &str,
+        index: usize,
+        provider: &Provider,
+        call: &mut F,
+    ) -> LlmResult<T>
+    where
+        F: FnMut(usize, Arc<dyn LlmClient>) -> Fut,
+        Fut: Future<Output = LlmResult<T>>,
+    {
+        match call(index, provider.client.clone()).await {
+            Ok(value) => {
+                provider.breaker().on_success();
+                match index {
+                    0 => log::info!("{name} served by {}", provider.name),
+                    _ => log::warn!("{name} served by fallback {}", provider.name),
+                }
+                Ok(value)
+            }
+            Err(error) if error.is_provider_failure() => {
+                log::warn!("{name} failed on {}: {error}", provider.name);
+                provider.breaker().on_failure(&self.policy);
+                Err(error)
+            }
+            // the request itself is wrong, other providers won't help,
+            // and it says nothing about the health of the provider
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Remove the model of the request for all providers but the first one.
+fn request_for(index: usize, request: &ChatRequest) -> ChatRequest {
+    let mut request = request.clone();
+    if index != 0 {
+        request.model = None;
+    }
+    request
+}
+
+#[async_trait::async_trait]
+impl LlmClient for FallbackClient {
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion> {
+        self.run("Chat request", |index, client| {
+            let request = request_for(index, &request);
+            async move { client.chat(request).await }
+        })
+        .await
+    }
+
+    /// Only establishing the stream fails over, errors in the middle of the stream are returned
+    /// as stream items.
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream> {
+        self.run("Chat stream request", |index, client| {
+            let request = request_for(index, &request);
+            async move { client.chat_stream(request).await }
+        })
+        .await
+    }
+
+    fn default_model(&self) -> &str {
+        self.primary().client.default_model()
+    }
+
+    fn default_max_tokens(&self) -> Option<usize> {
+        self.primary().client.default_max_tokens()
+    }
+
+    /// List models of the first healthy provider.
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        self.run("Models request", |_, client| async move {
+            client.list_models().await
+        })
+        .await
+    }
+}
@@ -1,5 +1,45 @@
+mod cache;
+mod cassette;
+mod chat;
+mod chat_completions;
+mod completion;
+mod context;
+mod embeddings;
+mod error;
+mod fallback;
+mod http;
 mod llm_client;
 mod mistral;
+mod models;
+mod moderation;
+mod openai_compat;
+mod rate_limit;
+mod retry;
+mod session;
+mod sse;
+mod structured;
+mod tools;
+mod transcription;
+mod usage;
 
+pub use cache::*;
+pub use cassette::*;
+pub use chat::*;
+pub use completion::*;
+pub use context::*;
+pub use embeddings::*;
+pub use error::*;
+pub use fallback::*;
+pub use http::*;
 pub use llm_client::*;
 pub use mistral::*;
+pub use models::*;
+pub use moderation::*;
+pub use openai_compat::*;
+pub use rate_limit::*;
+pub use retry::*;
+pub use session::*;
+pub use structured::*;
+pub use tools::*;
+pub use transcription::*;
+pub use usage::*;
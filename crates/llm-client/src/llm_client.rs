@@ -1,16 +1,233 @@
+use crate::{
+    context, ensure_model_listed, known_context_window, parse_structured, ChatMessage, ChatRequest,
+    Completion, FinishReason, LengthPolicy, LlmError, LlmResult, ModelInfo, ResponseFormat,
+    ToolExecutor, TruncationStrategy, Usage, DEFAULT_CONTEXT_WINDOW,
+};
+use futures::stream::BoxStream;
+use serde::de::DeserializeOwned;
+use std::{fmt::Debug, sync::Arc};
+
+/// How many times the model is asked to fix a reply that doesn't match the expected structure.
+pub const MAX_STRUCTURED_REASKS: usize = 2;
+
+/// How many times the model can call tools before giving the final reply.
+pub const MAX_TOOL_ROUNDS: usize = 5;
+
 pub trait ImplMessage: ToString + Send + Sync {}
 impl<T: ToString + Send + Sync> ImplMessage for T {}
 
+/// Item of a streaming completion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// Next piece of the response content.
+    Delta(String),
+    /// Tokens used by the request, usually sent at the end of the stream.
+    Usage(Usage),
+    /// Model stopped at the token limit, the streamed reply is partial. Only sent for requests
+    /// with [`LengthPolicy::Partial`], streams of other requests fail with [`LlmError::Truncated`].
+    Truncated,
+}
+
+/// Stream of content deltas produced by a streaming completion.
+pub type ContentStream = BoxStream<'static, LlmResult<StreamEvent>>;
+
+/// Chat completion provider.
+///
+/// The trait is object safe, so clients can be shared as `Arc<dyn LlmClient>`.
+/// Convenience methods are provided by [`LlmClientExt`].
+#[async_trait::async_trait]
+pub trait LlmClient: Debug + Send + Sync {
+    /// Send the request and return the assistant message together with the usage.
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion>;
+
+    /// Same as [`LlmClient::chat`], but yields the response content piece by piece
+    /// as the model generates it.
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream>;
+
+    /// Model used for requests that don't specify one.
+    fn default_model(&self) -> &str;
+
+    /// Maximum number of tokens generated for requests that don't specify one,
+    /// `None` if it's decided by the provider.
+    fn default_max_tokens(&self) -> Option<usize>;
+
+    /// List models available for the client.
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>>;
+}
+
 #[async_trait::async_trait]
-pub trait LlmClient {
-    async fn reset_chat(&mut self) -> eyre::Result<()>;
+pub trait LlmClientExt: LlmClient {
+    /// Send a single user message and return the response content.
+    async fn send_message_without_history<T: ImplMessage>(&self, message: T) -> LlmResult<String> {
+        let request = ChatRequest::new().with_user_message(message);
+        Ok(self.complete(request).await?.message.content)
+    }
+
+    /// Send the request and handle a reply cut by the token limit according to
+    /// the [`LengthPolicy`] of the request. Retries raise `max_tokens` of the request,
+    /// or the default of the client if the request doesn't set it.
+    async fn complete(&self, request: ChatRequest) -> LlmResult<Completion> {
+        let mut request = request;
+        let mut retries = 0;
 
-    async fn send_message_without_history<T: ImplMessage>(
+        loop {
+            let completion = self.chat(request.clone()).await?;
+            if !completion.is_truncated() {
+                return Ok(completion);
+            }
+
+            let can_retry = match request.length_policy {
+                LengthPolicy::Fail => false,
+                LengthPolicy::Partial => {
+                    log::warn!("Model reply was truncated, using the partial reply");
+                    return Ok(completion);
+                }
+                // a larger budget doesn't help if the reply hit the context window
+                LengthPolicy::Retry { max_retries } => {
+                    retries < max_retries && completion.finish_reason != FinishReason::ModelLength
+                }
+            };
+            let max_tokens = request.max_tokens.or(self.default_max_tokens());
+            let Some(max_tokens) = max_tokens.filter(|_| can_retry) else {
+                return completion.into_complete();
+            };
+
+            log::warn!(
+                "Model reply was truncated at {max_tokens} tokens, retrying with {}",
+                max_tokens * 2
+            );
+            request.max_tokens = Some(max_tokens * 2);
+            retries += 1;
+        }
+    }
+
+    /// Stream the response to a single user message.
+    async fn stream_message_without_history<T: ImplMessage>(
         &self,
         message: T,
-    ) -> eyre::Result<String>;
+    ) -> LlmResult<ContentStream> {
+        let request = ChatRequest::new().with_user_message(message);
+        self.chat_stream(request).await
+    }
+
+    /// Send the request in JSON mode and deserialize the reply into `T`.
+    ///
+    /// If the reply doesn't match `T`, the model is shown the error and asked again,
+    /// up to [`MAX_STRUCTURED_REASKS`] times.
+    async fn send_structured<T: DeserializeOwned>(&self, request: ChatRequest) -> LlmResult<T> {
+        let mut request = request.with_response_format(ResponseFormat::JsonObject);
+        let mut reasks = 0;
 
-    async fn send_message<T: ImplMessage>(&mut self, message: T) -> eyre::Result<String>;
+        loop {
+            let reply = self.complete(request.clone()).await?.message;
 
-    fn last_response(&self) -> Option<String>;
+            let error = match parse_structured::<T>(&reply.content) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            if reasks >= MAX_STRUCTURED_REASKS {
+                return Err(LlmError::InvalidStructuredOutput {
+                    source: error,
+                    content: reply.content,
+                });
+            }
+
+            log::warn!("Model reply doesn't match the expected format, re-asking: {error}");
+            log::debug!("Invalid structured reply: {}", reply.content);
+
+            request = request.with_message(reply).with_user_message(format!(
+                "Your reply is invalid: {error}. Reply again with only a JSON object in the requested format."
+            ));
+            reasks += 1;
+        }
+    }
+
+    /// Offer tools of the executor to the model and run the calls it makes, until it replies
+    /// without calling tools or [`MAX_TOOL_ROUNDS`] is reached.
+    ///
+    /// Returns messages added to the conversation: tool calls, their results and the final reply
+    /// as the last message.
+    async fn run_tools(
+        &self,
+        request: ChatRequest,
+        executor: &dyn ToolExecutor,
+    ) -> LlmResult<Vec<ChatMessage>> {
+        let mut request = request.with_tools(executor.tools());
+        let mut added = Vec::new();
+
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let reply = self.complete(request.clone()).await?.message;
+            added.push(reply.clone());
+
+            if reply.tool_calls.is_empty() {
+                return Ok(added);
+            }
+
+            request = request.with_message(reply.clone());
+            for call in &reply.tool_calls {
+                log::debug!("Model called tool: {:?}", call.function);
+
+                let result = match executor.call(&call.function).await {
+                    Ok(result) => result,
+                    Err(error) => {
+                        log::warn!("Tool {:?} failed: {error}", call.function.name);
+                        format!("Error: {error}")
+                    }
+                };
+
+                let message = ChatMessage::tool(call, result);
+                added.push(message.clone());
+                request = request.with_message(message);
+            }
+        }
+
+        Err(LlmError::ToolRoundsExceeded {
+            rounds: MAX_TOOL_ROUNDS,
+        })
+    }
+
+    /// Check that the model (or the default model if `None`) is available.
+    async fn validate_model(&self, model: Option<&str>) -> LlmResult<()> {
+        let models = self.list_models().await?;
+        ensure_model_listed(model.unwrap_or(self.default_model()), &models)
+    }
+
+    /// Context window of the model (or the default model if `None`) in tokens.
+    /// Uses the limit reported by the provider, then the limit of a well-known model,
+    /// then [`DEFAULT_CONTEXT_WINDOW`].
+    async fn context_window(&self, model: Option<&str>) -> u64 {
+        let model = model.unwrap_or(self.default_model());
+
+        let reported = match self.list_models().await {
+            Ok(models) => models
+                .iter()
+                .find(|info| info.id == model)
+                .and_then(|info| info.max_context_length),
+            Err(error) => {
+                log::debug!("Failed to get the context window of {model:?}: {error}");
+                None
+            }
+        };
+
+        reported
+            .map(|window| window as u64)
+            .or_else(|| known_context_window(model))
+            .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+    }
+
+    /// Make the request fit the context window of its model together with its `max_tokens`,
+    /// shrinking it with the strategy. Requests that fit are returned unchanged.
+    ///
+    /// Fails with [`LlmError::ContextWindowExceeded`] if the messages before the last one
+    /// don't fit the window.
+    async fn fit_context(
+        &self,
+        request: ChatRequest,
+        strategy: TruncationStrategy,
+    ) -> LlmResult<ChatRequest> {
+        context::fit_context(self, request, strategy).await
+    }
 }
+
+impl<T: LlmClient + ?Sized> LlmClientExt for T {}
@@ -1,137 +1,115 @@
-use crate::{ImplMessage, LlmClient};
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use crate::{
+    chat_completions::{self, Response},
+    embeddings, moderation, ChatRequest, Completion, ContentStream, EmbeddingClient, Embeddings,
+    LlmClient, LlmResult, ModelInfo, ModelsCache, Moderation, Moderator, RetryPolicy,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-
-const DEFAULT_MISTRAL_API_URL: &str = "https://api.mistral.ai/v1/chat/completions";
+use std::{
+    convert::Infallible,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    sync::Arc,
+};
+
+const DEFAULT_MISTRAL_API_URL: &str = "https://api.mistral.ai/v1";
+const CHAT_COMPLETIONS_PATH: &str = "/chat/completions";
+const DEFAULT_MISTRAL_EMBEDDING_MODEL: &str = "mistral-embed";
+const DEFAULT_MISTRAL_MODERATION_MODEL: &str = "mistral-moderation-latest";
 
 /// Mistral model type.
-#[derive(
-    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
-)]
+///
+/// Named variants are kept for convenience, any other model id (including fine-tuned models)
+/// can be used with [`MistralModelType::Custom`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
 pub enum MistralModelType {
     #[default]
-    #[serde(rename = "mistral-tiny")]
     Tiny,
-    #[serde(rename = "mistral-small")]
     Small,
-    #[serde(rename = "mistral-medium")]
     Medium,
+    Custom(String),
 }
 
-/// Mistral chat participant role.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub enum MistralRole {
-    #[serde(rename = "user")]
-    User,
-    #[serde(rename = "assistant")]
-    Assistant,
-    #[serde(rename = "system")]
-    System,
+impl MistralModelType {
+    /// Model id used by the API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Tiny => "mistral-tiny",
+            Self::Small => "mistral-small",
+            Self::Medium => "mistral-medium",
+            Self::Custom(model) => model,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct MistralMessage {
-    role: MistralRole,
-    content: String,
+impl Display for MistralModelType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
-impl MistralMessage {
-    pub fn user(content: impl ToString) -> Self {
-        Self {
-            role: MistralRole::User,
-            content: content.to_string(),
-        }
-    }
+impl FromStr for MistralModelType {
+    type Err = Infallible;
 
-    pub fn assistant(content: impl ToString) -> Self {
-        Self {
-            role: MistralRole::Assistant,
-            content: content.to_string(),
-        }
+    fn from_str(model: &str) -> Result<Self, Self::Err> {
+        Ok(match model {
+            "mistral-tiny" => Self::Tiny,
+            "mistral-small" => Self::Small,
+            "mistral-medium" => Self::Medium,
+            model => Self::Custom(model.to_string()),
+        })
     }
+}
 
-    pub fn system(content: impl ToString) -> Self {
-        Self {
-            role: MistralRole::System,
-            content: content.to_string(),
-        }
+impl From<&str> for MistralModelType {
+    fn from(model: &str) -> Self {
+        let Ok(model) = model.parse();
+        model
     }
 }
 
-impl From<(MistralRole, &str)> for MistralMessage {
-    fn from((role, content): (MistralRole, &str)) -> Self {
-        Self {
-            role,
-            content: content.to_string(),
-        }
+impl From<String> for MistralModelType {
+    fn from(model: String) -> Self {
+        model.as_str().into()
     }
 }
 
-pub trait ToHistory {
-    fn to_history(self) -> Vec<MistralMessage>;
+impl From<MistralModelType> for String {
+    fn from(model: MistralModelType) -> Self {
+        model.as_str().to_string()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct MistralClient {
     api_key: String,
-    history: Vec<MistralMessage>,
     api_url: String,
     client: reqwest::Client,
     model: MistralModelType,
     temperature: f64,
     max_tokens: Option<usize>,
     random_seed: Option<i64>,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
-struct Response {
-    id: String,
-    object: String,
-    created: i64,
-    model: String,
-    choices: Vec<ResponseChoice>,
-    usage: ResponseUsage,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-enum FinishReason {
-    #[serde(rename = "stop")]
-    Stop,
-    #[serde(rename = "length")]
-    Length,
-    #[serde(rename = "model_length")]
-    ModelLength,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
-struct ResponseChoice {
-    index: i64,
-    message: MistralMessage,
-    finish_reason: FinishReason,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
-struct ResponseUsage {
-    prompt_tokens: i64,
-    completion_tokens: i64,
-    total_tokens: i64,
+    retry_policy: RetryPolicy,
+    models_cache: ModelsCache,
+    embedding_model: String,
+    moderation_model: String,
 }
 
 impl MistralClient {
     pub fn new(api_key: impl ToString) -> Self {
         Self {
             api_key: api_key.to_string(),
-            history: Vec::new(),
             api_url: DEFAULT_MISTRAL_API_URL.to_string(),
             client: reqwest::Client::new(),
             model: MistralModelType::Tiny,
             temperature: 0.7,
             max_tokens: None,
             random_seed: None,
+            retry_policy: RetryPolicy::default(),
+            models_cache: ModelsCache::default(),
+            embedding_model: DEFAULT_MISTRAL_EMBEDDING_MODEL.to_string(),
+            moderation_model: DEFAULT_MISTRAL_MODERATION_MODEL.to_string(),
         }
     }
 
@@ -156,172 +134,205 @@ impl MistralClient {
         self
     }
 
-    /// Adds a message to the end of the history of the client.
-    pub fn with_message(mut self, message: MistralMessage) -> Self {
-        self.history.push(message);
+    /// Override the default Mistral API base URL (`https://api.mistral.ai/v1`).
+    /// Endpoint paths such as `/chat/completions` are appended to it.
+    ///
+    /// The method used to take the full chat completions endpoint, so a trailing
+    /// `/chat/completions` is removed from the URL.
+    pub fn with_api_url(mut self, api_url: impl ToString) -> Self {
+        let api_url = api_url.to_string();
+        let api_url = api_url.trim_end_matches('/');
+        self.api_url = match api_url.strip_suffix(CHAT_COMPLETIONS_PATH) {
+            Some(base_url) => {
+                log::warn!(
+                    "Mistral API URL {api_url:?} is the chat completions endpoint, \
+                    using its base URL {base_url:?}"
+                );
+                base_url.to_string()
+            }
+            None => api_url.to_string(),
+        };
+        self.models_cache = ModelsCache::default();
         self
     }
 
-    /// Adds a user message to the end of the history of the client.
-    pub fn with_user_message(self, message: impl ToString) -> Self {
-        self.with_message(MistralMessage {
-            role: MistralRole::User,
-            content: message.to_string(),
-        })
+    /// Allows to override the default Mistral model type.
+    pub fn with_model(mut self, model: impl Into<MistralModelType>) -> Self {
+        self.model = model.into();
+        self
     }
 
-    /// Adds an assistant message to the end of the history of the client.
-    pub fn with_assistant_message(self, message: impl ToString) -> Self {
-        self.with_message(MistralMessage {
-            role: MistralRole::Assistant,
-            content: message.to_string(),
-        })
+    pub fn model(&self) -> &MistralModelType {
+        &self.model
     }
 
-    /// Set history of the client.
-    /// If there is already a history, it will be replaced (including system messages).
-    pub fn with_history(mut self, history: impl ToHistory) -> Self {
-        self.history = history.to_history();
+    /// Set the model used to compute embeddings. Default is `mistral-embed`.
+    pub fn with_embedding_model(mut self, model: impl ToString) -> Self {
+        self.embedding_model = model.to_string();
         self
     }
 
-    /// Inserts system message to the beginning of the history of the client.
-    /// If there is already a system message, it will be replaced.
-    pub fn with_system_message(mut self, message: impl ToString) -> Self {
-        let message = message.to_string();
-
-        match self.history.first_mut() {
-            Some(MistralMessage {
-                role: MistralRole::System,
-                content,
-            }) => {
-                *content = message;
-            }
-            _ => {
-                self.history.insert(0, MistralMessage::system(message));
-            }
-        };
-
+    /// Set the model used to moderate texts. Default is `mistral-moderation-latest`.
+    pub fn with_moderation_model(mut self, model: impl ToString) -> Self {
+        self.moderation_model = model.to_string();
         self
     }
 
-    /// Override the default Mistral API URL.
-    pub fn with_api_url(mut self, api_url: impl ToString) -> Self {
-        self.api_url = api_url.to_string();
+    /// Use the HTTP client, e.g. one built from [`crate::HttpSettings`] and shared with other
+    /// clients. Default is a client with reqwest defaults.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
         self
     }
 
-    /// Allows to override the default Mistral model type.
-    pub fn with_model(mut self, model: MistralModelType) -> Self {
-        self.model = model;
+    /// Set the policy used to retry failed requests. Default is [`RetryPolicy::default`].
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
         self
     }
 
-    async fn send_message_inner(&self, message: MistralMessage) -> eyre::Result<Response> {
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {}", self.api_key)).unwrap(),
-        );
-        headers.insert("Accept", HeaderValue::from_static("application/json"));
+    fn build_request(&self, request: &ChatRequest, stream: bool) -> reqwest::RequestBuilder {
+        log::debug!("Sending request to Mistral: {:?}", request);
 
-        log::debug!("Sending message to Mistral: {:?}", message);
-
-        // FIXME maybe we should clone the entire history here
-        let mut history = self.history.clone();
-
-        history.push(message.clone());
-
-        let body = json!({
-            "model": "mistral-tiny",
-            "messages": history,
-            "temperature": self.temperature,
-            "max_tokens": self.max_tokens,
-            "random_seed": self.random_seed,
+        let mut body = json!({
+            "model": request.model.as_deref().unwrap_or(self.model.as_str()),
+            "messages": request.messages,
+            "temperature": request.temperature.unwrap_or(self.temperature),
+            "stream": stream,
         });
+        if let Some(max_tokens) = request.max_tokens.or(self.max_tokens) {
+            body["max_tokens"] = max_tokens.into();
+        }
+        if let Some(random_seed) = request.random_seed.or(self.random_seed) {
+            body["random_seed"] = random_seed.into();
+        }
+        if let Some(safe_prompt) = request.safe_prompt {
+            body["safe_prompt"] = safe_prompt.into();
+        }
+        chat_completions::insert_sampling_params(&mut body, request);
+        if let Some(response_format) = request.response_format {
+            body["response_format"] = json!(response_format);
+        }
+        if !request.tools.is_empty() {
+            body["tools"] = json!(request.tools);
+        }
+        if let Some(tool_choice) = request.tool_choice {
+            body["tool_choice"] = json!(tool_choice);
+        }
 
-        let response = self
-            .client
-            .post(&self.api_url)
-            .headers(headers)
+        self.client
+            .post(format!("{}{CHAT_COMPLETIONS_PATH}", self.api_url))
+            .bearer_auth(&self.api_key)
             .json(&body)
-            .send()
-            .await?;
-
-        let str_resp = response.text().await?;
-
-        log::debug!("Mistral response: {}", str_resp);
-
-        let response: Response = serde_json::from_str(&str_resp)?;
-
-        Ok(response)
     }
 }
 
 #[async_trait::async_trait]
 impl LlmClient for MistralClient {
-    async fn reset_chat(&mut self) -> eyre::Result<()> {
-        self.history.clear();
-        Ok(())
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion> {
+        let response: Response = chat_completions::send(
+            || self.build_request(&request, false),
+            &self.retry_policy,
+            "Mistral",
+        )
+        .await?;
+
+        response.into_completion()
     }
 
-    async fn send_message_without_history<T: ImplMessage>(
-        &self,
-        message: T,
-    ) -> eyre::Result<String> {
-        let response = self
-            .send_message_inner(MistralMessage::user(message))
-            .await?;
-
-        let ResponseChoice { message, .. } = response
-            .choices
-            .first()
-            .expect("choise should exist")
-            .clone();
-
-        let MistralMessage { content, .. } = message.clone();
-
-        Ok(content)
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream> {
+        chat_completions::stream(
+            || self.build_request(&request, true),
+            &self.retry_policy,
+            request.length_policy,
+            "Mistral",
+        )
+        .await
     }
 
-    async fn send_message<T: ImplMessage>(&mut self, user_message: T) -> eyre::Result<String> {
-        let user_message = MistralMessage::user(user_message);
-        let response = self.send_message_inner(user_message.clone()).await?;
-
-        let ResponseChoice {
-            message: response_message,
-            ..
-        } = response
-            .choices
-            .first()
-            .expect("choise should exist")
-            .clone();
+    fn default_model(&self) -> &str {
+        self.model.as_str()
+    }
 
-        let content = response_message.content.clone();
+    fn default_max_tokens(&self) -> Option<usize> {
+        self.max_tokens
+    }
 
-        self.history.push(user_message);
-        self.history.push(response_message);
+    /// List models available for the API key.
+    /// The result is cached and shared between clones of the client.
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        self.models_cache
+            .get_or_fetch(
+                &self.client,
+                &self.api_url,
+                Some(&self.api_key),
+                &self.retry_policy,
+            )
+            .await
+    }
+}
 
-        Ok(content)
+#[async_trait::async_trait]
+impl EmbeddingClient for MistralClient {
+    async fn embed(&self, texts: &[String]) -> LlmResult<Embeddings> {
+        embeddings::embed(
+            &self.client,
+            &self.api_url,
+            Some(&self.api_key),
+            &self.embedding_model,
+            texts,
+            &self.retry_policy,
+            "Mistral",
+        )
+        .await
     }
 
-    fn last_response(&self) -> Option<String> {
-        self.history.last().map(|item| item.content.clone())
+    fn embedding_model(&self) -> &str {
+        &self.embedding_model
     }
 }
 
-impl ToHistory for Vec<MistralMessage> {
-    fn to_history(self) -> Vec<MistralMessage> {
-        self
+#[async_trait::async_trait]
+impl Moderator for MistralClient {
+    async fn moderate(&self, text: &str) -> LlmResult<Moderation> {
+        moderation::moderate(
+            &self.client,
+            &self.api_url,
+            Some(&self.api_key),
+            &self.moderation_model,
+            text,
+            &self.retry_policy,
+            "Mistral",
+        )
+        .await
     }
 }
 
-impl<T> ToHistory for &[T]
-where
-    T: Into<MistralMessage> + Clone,
-{
-    fn to_history(self) -> Vec<MistralMessage> {
-        self.iter().map(|item| item.clone().into()).collect()
-    }
+#[test]
+fn test_model_type_serde() {
+    let models = [
+        MistralModelType::Tiny,
+        MistralModelType::Custom("open-mistral-nemo".to_string()),
+    ];
+    let json = serde_json::to_string(&models).unwrap();
+
+    assert_eq!(json, r#"["mistral-tiny","open-mistral-nemo"]"#);
+    assert_eq!(
+        serde_json::from_str::<Vec<MistralModelType>>(&json).unwrap(),
+        models
+    );
+    assert_eq!(
+        MistralModelType::from("mistral-small"),
+        MistralModelType::Small
+    );
+}
+
+#[test]
+fn test_api_url() {
+    let client = MistralClient::new("token").with_api_url("http://localhost:8080/v1/");
+    assert_eq!(client.api_url, "http://localhost:8080/v1");
+
+    let client =
+        MistralClient::new("token").with_api_url("https://api.mistral.ai/v1/chat/completions");
+    assert_eq!(client.api_url, DEFAULT_MISTRAL_API_URL);
 }
@@ -0,0 +1,89 @@
+use crate::{error_for_status, LlmError, LlmResult, RetryPolicy};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Mutex, time::Instant};
+
+/// How long the list of available models is reused before it is requested again.
+const MODELS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Model available for the API key, as returned by the `/models` endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    #[serde(default)]
+    pub owned_by: Option<String>,
+    #[serde(default)]
+    pub created: Option<i64>,
+    #[serde(default)]
+    pub max_context_length: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ModelList {
+    data: Vec<ModelInfo>,
+}
+
+/// Models list with the time it was fetched at.
+type CachedModels = (Instant, Arc<Vec<ModelInfo>>);
+
+/// Cached list of models, shared between clones of a client.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ModelsCache {
+    inner: Arc<Mutex<Option<CachedModels>>>,
+}
+
+impl ModelsCache {
+    /// Return cached models or fetch them from `{api_url}/models`.
+    pub async fn get_or_fetch(
+        &self,
+        client: &reqwest::Client,
+        api_url: &str,
+        api_key: Option<&str>,
+        retry_policy: &RetryPolicy,
+    ) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        let mut cache = self.inner.lock().await;
+
+        if let Some((fetched_at, models)) = cache.as_ref() {
+            if fetched_at.elapsed() < MODELS_CACHE_TTL {
+                return Ok(models.clone());
+            }
+        }
+
+        let url = format!("{api_url}/models");
+        let body = retry_policy
+            .run("Models request", || async {
+                let mut request = client.get(&url);
+                if let Some(api_key) = api_key {
+                    request = request.bearer_auth(api_key);
+                }
+
+                let response = request.send().await?;
+                let response = error_for_status(response).await?;
+
+                LlmResult::Ok(response.text().await?)
+            })
+            .await?;
+
+        log::debug!("Models response: {}", body);
+
+        let ModelList { data } =
+            serde_json::from_str(&body).map_err(|e| LlmError::malformed(e, &body))?;
+        let models = Arc::new(data);
+
+        *cache = Some((Instant::now(), models.clone()));
+
+        Ok(models)
+    }
+}
+
+/// Fail with [`LlmError::UnknownModel`] if the model is not in the list.
+pub(crate) fn ensure_model_listed(model: &str, models: &[ModelInfo]) -> LlmResult<()> {
+    if models.iter().any(|info| info.id == model) {
+        return Ok(());
+    }
+
+    Err(LlmError::UnknownModel {
+        model: model.to_string(),
+        available: models.iter().map(|info| info.id.clone()).collect(),
+    })
+}
@@ -0,0 +1,313 @@
+use crate::{error_for_status, LlmError, LlmResult, RetryPolicy};
+use reqwest::header::{HeaderValue, ACCEPT};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
+};
+
+/// Category of harmful content, as defined by Mistral moderation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationCategory {
+    Sexual,
+    HateAndDiscrimination,
+    ViolenceAndThreats,
+    DangerousAndCriminalContent,
+    Selfharm,
+    Health,
+    Financial,
+    Law,
+    Pii,
+}
+
+impl ModerationCategory {
+    pub const ALL: [Self; 9] = [
+        Self::Sexual,
+        Self::HateAndDiscrimination,
+        Self::ViolenceAndThreats,
+        Self::DangerousAndCriminalContent,
+        Self::Selfharm,
+        Self::Health,
+        Self::Financial,
+        Self::Law,
+        Self::Pii,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sexual => "sexual",
+            Self::HateAndDiscrimination => "hate_and_discrimination",
+            Self::ViolenceAndThreats => "violence_and_threats",
+            Self::DangerousAndCriminalContent => "dangerous_and_criminal_content",
+            Self::Selfharm => "selfharm",
+            Self::Health => "health",
+            Self::Financial => "financial",
+            Self::Law => "law",
+            Self::Pii => "pii",
+        }
+    }
+}
+
+impl Display for ModerationCategory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ModerationCategory {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.as_str() == value)
+            .ok_or_else(|| format!("unknown moderation category {value:?}"))
+    }
+}
+
+/// Result of checking a text.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Moderation {
+    /// Categories the text belongs to, in the order of [`ModerationCategory::ALL`].
+    pub flagged: Vec<ModerationCategory>,
+    /// Probability of every category, if the moderator reports it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scores: BTreeMap<ModerationCategory, f64>,
+}
+
+impl Moderation {
+    pub fn is_flagged(&self) -> bool {
+        !self.flagged.is_empty()
+    }
+}
+
+/// Classifier of harmful content.
+#[async_trait::async_trait]
+pub trait Moderator: Debug + Send + Sync {
+    async fn moderate(&self, text: &str) -> LlmResult<Moderation>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Response {
+    results: Vec<ResponseItem>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ResponseItem {
+    categories: HashMap<String, bool>,
+    #[serde(default)]
+    category_scores: HashMap<String, f64>,
+}
+
+impl From<ResponseItem> for Moderation {
+    fn from(item: ResponseItem) -> Self {
+        // categories unknown to this version are ignored
+        let category = |name: &str| name.parse::<ModerationCategory>().ok();
+
+        let mut flagged = item
+            .categories
+            .iter()
+            .filter(|(_, flagged)| **flagged)
+            .filter_map(|(name, _)| category(name))
+            .collect::<Vec<_>>();
+        flagged.sort();
+
+        let scores = item
+            .category_scores
+            .iter()
+            .filter_map(|(name, score)| Some((category(name)?, *score)))
+            .collect();
+
+        Self { flagged, scores }
+    }
+}
+
+/// Send the text to the `{api_url}/moderations` endpoint.
+/// `name` identifies the provider in logs.
+pub(crate) async fn moderate(
+    client: &reqwest::Client,
+    api_url: &str,
+    api_key: Option<&str>,
+    model: &str,
+    text: &str,
+    retry_policy: &RetryPolicy,
+    name: &str,
+) -> LlmResult<Moderation> {
+    let url = format!("{api_url}/moderations");
+    let body = json!({
+        "model": model,
+        "input": [text],
+    });
+
+    let str_resp = retry_policy
+        .run(&format!("{name} moderation request"), || async {
+            let mut request = client
+                .post(&url)
+                .header(ACCEPT, HeaderValue::from_static("application/json"))
+                .json(&body);
+            if let Some(api_key) = api_key {
+                request = request.bearer_auth(api_key);
+            }
+
+            let response = error_for_status(request.send().await?).await?;
+
+            LlmResult::Ok(response.text().await?)
+        })
+        .await?;
+
+    let Response { results } =
+        serde_json::from_str(&str_resp).map_err(|e| LlmError::malformed(e, &str_resp))?;
+
+    results
+        .into_iter()
+        .next()
+        .map(Moderation::from)
+        // a result is returned for every input
+        .ok_or(LlmError::EmptyModerationResults)
+}
+
+/// Moderator flagging texts that contain listed words or phrases, without calling any API.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleModerator {
+    rules: Vec<(ModerationCategory, String)>,
+}
+
+impl RuleModerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Flag texts containing the phrase with the category.
+    /// Phrases match whole words, ignoring case.
+    pub fn with_rule(mut self, category: ModerationCategory, phrase: impl ToString) -> Self {
+        let phrase = normalize(&phrase.to_string());
+        if !phrase.is_empty() {
+            self.rules.push((category, phrase));
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// Lowercase words of the text separated by single spaces, with a space at both ends,
+/// so phrases can be matched by whole words with `contains`.
+fn normalize(text: &str) -> String {
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+
+    if words.is_empty() {
+        return String::new();
+    }
+
+    format!(" {} ", words.join(" "))
+}
+
+#[async_trait::async_trait]
+impl Moderator for RuleModerator {
+    async fn moderate(&self, text: &str) -> LlmResult<Moderation> {
+        let text = normalize(text);
+
+        let mut flagged = self
+            .rules
+            .iter()
+            .filter(|(_, phrase)| text.contains(phrase.as_str()))
+            .map(|(category, _)| *category)
+            .collect::<Vec<_>>();
+        flagged.sort();
+        flagged.dedup();
+
+        Ok(Moderation {
+            flagged,
+            scores: BTreeMap::new(),
+        })
+    }
+}
+
+/// Parse rules with a line per category: `category: phrase, another phrase`.
+/// Empty lines and lines starting with `#` are skipped.
+impl FromStr for RuleModerator {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut moderator = Self::new();
+
+        let lines = value
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        for line in lines {
+            let (category, phrases) = line
+                .split_once(':')
+                .ok_or_else(|| format!("invalid rule {line:?}, expected `category: phrases`"))?;
+            let category = category.trim().parse()?;
+
+            for phrase in phrases.split(',') {
+                moderator = moderator.with_rule(category, phrase);
+            }
+        }
+
+        Ok(moderator)
+    }
+}
+
+#[tokio::test]
+async fn test_rule_moderator() {
+    let moderator: RuleModerator = "
+        # rules for tests
+        violence_and_threats: kill you, bomb
+        pii: password
+    "
+    .parse()
+    .unwrap();
+
+    let moderation = moderator
+        .moderate("I will KILL you, here is my password!")
+        .await
+        .unwrap();
+    assert_eq!(
+        moderation.flagged,
+        [
+            ModerationCategory::ViolenceAndThreats,
+            ModerationCategory::Pii
+        ]
+    );
+
+    // only whole words match
+    let moderation = moderator.moderate("Bombay skill").await.unwrap();
+    assert!(!moderation.is_flagged());
+
+    assert!("unknown: word".parse::<RuleModerator>().is_err());
+    assert!("no separator".parse::<RuleModerator>().is_err());
+}
+
+#[test]
+fn test_parse_moderation_response() {
+    let response: Response = serde_json::from_str(
+        r#"{
+            "id": "mod-1",
+            "model": "mistral-moderation-latest",
+            "results": [{
+                "categories": {"sexual": false, "pii": true, "selfharm": true, "new_one": true},
+                "category_scores": {"sexual": 0.01, "pii": 0.9, "selfharm": 0.8, "new_one": 0.7}
+            }]
+        }"#,
+    )
+    .unwrap();
+
+    let moderation = Moderation::from(response.results[0].clone());
+    assert_eq!(
+        moderation.flagged,
+        [ModerationCategory::Selfharm, ModerationCategory::Pii]
+    );
+    assert_eq!(moderation.scores[&ModerationCategory::Pii], 0.9);
+    assert_eq!(moderation.scores.len(), 3);
+}
@@ -0,0 +1,213 @@
+use crate::{
+    chat_completions::{self, Response},
+    embeddings, ChatRequest, Completion, ContentStream, EmbeddingClient, Embeddings, LlmClient,
+    LlmResult, ModelInfo, ModelsCache, RetryPolicy,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Client for servers implementing OpenAI `/v1/chat/completions` API,
+/// such as llama.cpp server, vLLM or Ollama.
+#[derive(Debug, Clone)]
+pub struct OpenAiCompatClient {
+    api_key: Option<String>,
+    api_url: String,
+    client: reqwest::Client,
+    model: String,
+    temperature: f64,
+    max_tokens: Option<usize>,
+    random_seed: Option<i64>,
+    retry_policy: RetryPolicy,
+    models_cache: ModelsCache,
+    embedding_model: String,
+}
+
+impl OpenAiCompatClient {
+    /// Create a client for the API with the given base URL (e.g. `http://localhost:8080/v1`).
+    /// Endpoint paths such as `/chat/completions` are appended to it.
+    pub fn new(api_url: impl ToString) -> Self {
+        Self {
+            api_key: None,
+            api_url: api_url.to_string().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            model: "default".to_string(),
+            temperature: 0.7,
+            max_tokens: None,
+            random_seed: None,
+            retry_policy: RetryPolicy::default(),
+            models_cache: ModelsCache::default(),
+            embedding_model: "default".to_string(),
+        }
+    }
+
+    /// Set the API key sent as a bearer token. Local servers usually don't need it.
+    pub fn with_api_key(mut self, api_key: impl Into<Option<String>>) -> Self {
+        self.api_key = api_key.into();
+        self.models_cache = ModelsCache::default();
+        self
+    }
+
+    /// Set the temperature of the model. Default is 0.7.
+    pub fn with_temperature(mut self, temperature: impl Into<f64>) -> Self {
+        self.temperature = temperature.into();
+        self
+    }
+
+    /// Set the maximum number of tokens to generate. Default is unlimited.
+    pub fn with_max_tokens(mut self, max_tokens: impl Into<Option<usize>>) -> Self {
+        self.max_tokens = match max_tokens.into() {
+            Some(0) => None,
+            other => other,
+        };
+        self
+    }
+
+    /// Set the random seed for the model. Default is None.
+    pub fn with_random_seed(mut self, random_seed: impl Into<Option<i64>>) -> Self {
+        self.random_seed = random_seed.into();
+        self
+    }
+
+    /// Override the API base URL.
+    pub fn with_api_url(mut self, api_url: impl ToString) -> Self {
+        self.api_url = api_url.to_string().trim_end_matches('/').to_string();
+        self.models_cache = ModelsCache::default();
+        self
+    }
+
+    /// Set the model name. Default is `default`, which is accepted by servers serving
+    /// a single model (e.g. llama.cpp), others require the actual model name.
+    pub fn with_model(mut self, model: impl ToString) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Set the model used to compute embeddings. Default is `default`.
+    pub fn with_embedding_model(mut self, model: impl ToString) -> Self {
+        self.embedding_model = model.to_string();
+        self
+    }
+
+    /// Use the HTTP client, e.g. one built from [`crate::HttpSettings`] and shared with other
+    /// clients. Default is a client with reqwest defaults.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the policy used to retry failed requests. Default is [`RetryPolicy::default`].
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    fn build_request(&self, request: &ChatRequest, stream: bool) -> reqwest::RequestBuilder {
+        log::debug!("Sending request to OpenAI compatible API: {:?}", request);
+
+        let mut body = json!({
+            "model": request.model.as_deref().unwrap_or(&self.model),
+            "messages": request.messages,
+            "temperature": request.temperature.unwrap_or(self.temperature),
+            "stream": stream,
+        });
+        if stream {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        // some servers reject `null` values, so optional parameters are omitted instead
+        if let Some(max_tokens) = request.max_tokens.or(self.max_tokens) {
+            body["max_tokens"] = max_tokens.into();
+        }
+        if let Some(seed) = request.random_seed.or(self.random_seed) {
+            body["seed"] = seed.into();
+        }
+        chat_completions::insert_sampling_params(&mut body, request);
+        if let Some(response_format) = request.response_format {
+            body["response_format"] = json!(response_format);
+        }
+        if !request.tools.is_empty() {
+            body["tools"] = json!(request.tools);
+        }
+        if let Some(tool_choice) = request.tool_choice {
+            body["tool_choice"] = json!(tool_choice);
+        }
+
+        let request = self
+            .client
+            .post(format!("{}/chat/completions", self.api_url))
+            .json(&body);
+
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LlmClient for OpenAiCompatClient {
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion> {
+        let response: Response = chat_completions::send(
+            || self.build_request(&request, false),
+            &self.retry_policy,
+            "OpenAI compatible API",
+        )
+        .await?;
+
+        response.into_completion()
+    }
+
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream> {
+        chat_completions::stream(
+            || self.build_request(&request, true),
+            &self.retry_policy,
+            request.length_policy,
+            "OpenAI compatible API",
+        )
+        .await
+    }
+
+    fn default_model(&self) -> &str {
+        &self.model
+    }
+
+    fn default_max_tokens(&self) -> Option<usize> {
+        self.max_tokens
+    }
+
+    /// List models served by the API.
+    /// The result is cached and shared between clones of the client.
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        self.models_cache
+            .get_or_fetch(
+                &self.client,
+                &self.api_url,
+                self.api_key.as_deref(),
+                &self.retry_policy,
+            )
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingClient for OpenAiCompatClient {
+    async fn embed(&self, texts: &[String]) -> LlmResult<Embeddings> {
+        embeddings::embed(
+            &self.client,
+            &self.api_url,
+            self.api_key.as_deref(),
+            &self.embedding_model,
+            texts,
+            &self.retry_policy,
+            "OpenAI compatible API",
+        )
+        .await
+    }
+
+    fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+}
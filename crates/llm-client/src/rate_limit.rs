@@ -0,0 +1,456 @@
+use crate::{
+    context::completion_tokens, estimate_prompt_tokens, estimate_tokens, ChatRequest, Completion,
+    ContentStream, LlmClient, LlmResult, ModelInfo, Moderation, Moderator, StreamEvent,
+};
+use futures::StreamExt;
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::Notify, time::Instant};
+
+/// Order in which queued requests are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Requests nobody is waiting for right now, e.g. periodic jobs.
+    Background,
+    /// Requests a user is waiting for, they are sent before all background requests.
+    #[default]
+    Interactive,
+}
+
+/// Limits of a provider account. Limits that are not set are not enforced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    requests_per_minute: Option<u32>,
+    tokens_per_minute: Option<u64>,
+    max_concurrent_requests: Option<usize>,
+}
+
+impl RateLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_requests_per_minute(mut self, requests: impl Into<Option<u32>>) -> Self {
+        self.requests_per_minute = requests.into().map(|requests| requests.max(1));
+        self
+    }
+
+    /// Set the limit of prompt and completion tokens per minute.
+    pub fn with_tokens_per_minute(mut self, tokens: impl Into<Option<u64>>) -> Self {
+        self.tokens_per_minute = tokens.into().map(|tokens| tokens.max(1));
+        self
+    }
+
+    pub fn with_max_concurrent_requests(mut self, requests: impl Into<Option<usize>>) -> Self {
+        self.max_concurrent_requests = requests.into().map(|requests| requests.max(1));
+        self
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Number of requests waiting in a [`RateLimiter`] and being sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueDepth {
+    pub interactive: usize,
+    pub background: usize,
+    /// Requests that were sent and haven't completed yet.
+    pub in_flight: usize,
+}
+
+impl QueueDepth {
+    /// Number of waiting requests.
+    pub fn queued(&self) -> usize {
+        self.interactive + self.background
+    }
+}
+
+/// Bucket that is refilled from empty to full in a minute.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(capacity: f64, now: Instant) -> Self {
+        Self {
+            capacity,
+            available: capacity,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.available = (self.available + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    /// Time until the amount is available, `None` if it's available now.
+    /// Amounts larger than the capacity only wait for a full bucket.
+    fn wait_time(&self, amount: f64) -> Option<Duration> {
+        let missing = amount.min(self.capacity) - self.available;
+        (missing > 0.0).then(|| Duration::from_secs_f64(missing * 60.0 / self.capacity))
+    }
+
+    /// Add the amount to the bucket, negative amounts take from it.
+    fn add(&mut self, amount: f64) {
+        self.available = (self.available + amount).min(self.capacity);
+    }
+}
+
+/// Position in the queue, higher priorities first, then in order of arrival.
+type Ticket = (Reverse<Priority>, u64);
+
+#[derive(Debug)]
+struct State {
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+    in_flight: usize,
+    queue: BTreeSet<Ticket>,
+    next_ticket: u64,
+}
+
+impl State {
+    /// Take the resources for the request if it's first in the queue and they are available.
+    /// Otherwise returns how long to wait, `None` if until another request completes.
+    fn try_acquire(
+        &mut self,
+        ticket: &Ticket,
+        tokens: u64,
+        limits: &RateLimits,
+    ) -> Result<(), Option<Duration>> {
+        if self.queue.first() != Some(ticket) {
+            return Err(None);
+        }
+        if limits
+            .max_concurrent_requests
+            .is_some_and(|max| self.in_flight >= max)
+        {
+            return Err(None);
+        }
+
+        let now = Instant::now();
+        let buckets = [(&mut self.requests, 1.0), (&mut self.tokens, tokens as f64)];
+        let mut wait = None;
+        for (bucket, amount) in buckets {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                wait = wait.max(bucket.wait_time(amount));
+            }
+        }
+        if wait.is_some() {
+            return Err(wait);
+        }
+
+        if let Some(bucket) = &mut self.requests {
+            bucket.add(-1.0);
+        }
+        if let Some(bucket) = &mut self.tokens {
+            bucket.add(-(tokens as f64));
+        }
+        self.in_flight += 1;
+        self.queue.remove(ticket);
+
+        Ok(())
+    }
+}
+
+/// Limiter of requests and tokens per minute shared by all clients of a provider account.
+///
+/// Requests wait in a single queue: interactive requests are always sent before background
+/// ones, requests of the same priority are sent in order of arrival.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        let now = Instant::now();
+        let bucket = |capacity: f64| TokenBucket::per_minute(capacity, now);
+
+        Self {
+            state: Mutex::new(State {
+                requests: limits.requests_per_minute.map(|n| bucket(n as f64)),
+                tokens: limits.tokens_per_minute.map(|n| bucket(n as f64)),
+                in_flight: 0,
+                queue: BTreeSet::new(),
+                next_ticket: 0,
+            }),
+            limits,
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Current number of waiting and running requests.
+    pub fn queue_depth(&self) -> QueueDepth {
+        let state = self.state();
+        let interactive = state
+            .queue
+            .iter()
+            .filter(|(Reverse(priority), _)| *priority == Priority::Interactive)
+            .count();
+
+        QueueDepth {
+            interactive,
+            background: state.queue.len() - interactive,
+            in_flight: state.in_flight,
+        }
+    }
+
+    /// Wait until a request with the estimated number of tokens may be sent.
+    pub async fn acquire(self: &Arc<Self>, priority: Priority, tokens: u64) -> RatePermit {
+        let started_at = Instant::now();
+        let ticket = {
+            let mut state = self.state();
+            let ticket = (Reverse(priority), state.next_ticket);
+            state.next_ticket += 1;
+            state.queue.insert(ticket);
+            ticket
+        };
+        let mut queued = QueueGuard {
+            limiter: self,
+            ticket: Some(ticket),
+        };
+
+        loop {
+            // subscribe before checking, so a release between the check and the wait isn't lost
+            let mut notified = pin!(self.notify.notified());
+            notified.as_mut().enable();
+
+            let result = self.state().try_acquire(&ticket, tokens, &self.limits);
+            match result {
+                Ok(()) => break,
+                Err(Some(wait)) => {
+                    tokio::select! {
+                        _ = notified => {}
+                        _ = tokio::time::sleep(wait) => {}
+                    }
+                }
+                Err(None) => notified.await,
+            }
+        }
+
+        queued.ticket = None;
+        // the next request in the queue may be allowed too
+        self.notify.notify_waiters();
+
+        let waited = started_at.elapsed();
+        if waited > Duration::from_millis(100) {
+            log::debug!(
+                "LLM request waited {waited:?} in the rate limiter, {:?}",
+                self.queue_depth()
+            );
+        }
+
+        RatePermit {
+            limiter: self.clone(),
+            reserved_tokens: tokens,
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Removes the ticket from the queue if the waiting request is cancelled.
+struct QueueGuard<'a> {
+    limiter: &'a RateLimiter,
+    ticket: Option<Ticket>,
+}
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            self.limiter.state().queue.remove(&ticket);
+            self.limiter.notify.notify_waiters();
+        }
+    }
+}
+
+/// Permission to send a request. The request is considered completed when the permit is dropped.
+#[derive(Debug)]
+pub struct RatePermit {
+    limiter: Arc<RateLimiter>,
+    reserved_tokens: u64,
+}
+
+impl RatePermit {
+    /// Replace the estimated number of tokens with the number the request actually used.
+    pub fn record_usage(&mut self, tokens: u64) {
+        if let Some(bucket) = &mut self.limiter.state().tokens {
+            bucket.add(self.reserved_tokens as f64 - tokens as f64);
+        }
+        self.reserved_tokens = tokens;
+        self.limiter.notify.notify_waiters();
+    }
+}
+
+impl Drop for RatePermit {
+    fn drop(&mut self) {
+        self.limiter.state().in_flight -= 1;
+        self.limiter.notify.notify_waiters();
+    }
+}
+
+/// Client that waits for a [`RateLimiter`] before sending requests.
+///
+/// A request reserves its prompt and `max_tokens` in the token bucket, the reservation is
+/// corrected once the usage is known. Retries of the inner client are not counted separately.
+#[derive(Debug)]
+pub struct RateLimitedClient {
+    inner: Arc<dyn LlmClient>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedClient {
+    pub fn new(inner: Arc<dyn LlmClient>, limiter: Arc<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+
+    pub fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+}
+
+/// Tokens the request is expected to use.
+fn estimate_request_tokens(request: &ChatRequest) -> u64 {
+    estimate_prompt_tokens(&request.messages) + completion_tokens(request)
+}
+
+#[async_trait::async_trait]
+impl LlmClient for RateLimitedClient {
+    async fn chat(&self, request: ChatRequest) -> LlmResult<Completion> {
+        let tokens = estimate_request_tokens(&request);
+        let mut permit = self.limiter.acquire(request.priority, tokens).await;
+
+        let completion = self.inner.chat(request).await?;
+        permit.record_usage(completion.usage.total_tokens);
+
+        Ok(completion)
+    }
+
+    /// The permit is held until the stream is dropped.
+    async fn chat_stream(&self, request: ChatRequest) -> LlmResult<ContentStream> {
+        let tokens = estimate_request_tokens(&request);
+        let mut permit = self.limiter.acquire(request.priority, tokens).await;
+
+        let stream = self.inner.chat_stream(request).await?;
+        let stream = stream.map(move |event| {
+            if let Ok(StreamEvent::Usage(usage)) = &event {
+                permit.record_usage(usage.total_tokens);
+            }
+            event
+        });
+
+        Ok(stream.boxed())
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn default_max_tokens(&self) -> Option<usize> {
+        self.inner.default_max_tokens()
+    }
+
+    async fn list_models(&self) -> LlmResult<Arc<Vec<ModelInfo>>> {
+        let _permit = self.limiter.acquire(Priority::default(), 0).await;
+        self.inner.list_models().await
+    }
+}
+
+/// Moderator that waits for a [`RateLimiter`] before sending requests, for moderation APIs of
+/// the same provider account as the LLM clients.
+#[derive(Debug)]
+pub struct RateLimitedModerator {
+    inner: Arc<dyn Moderator>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedModerator {
+    pub fn new(inner: Arc<dyn Moderator>, limiter: Arc<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait::async_trait]
+impl Moderator for RateLimitedModerator {
+    /// Users wait for the checks of their messages, so they are sent as interactive requests.
+    async fn moderate(&self, text: &str) -> LlmResult<Moderation> {
+        let _permit = self
+            .limiter
+            .acquire(Priority::Interactive, estimate_tokens(text))
+            .await;
+        self.inner.moderate(text).await
+    }
+}
+
+#[test]
+fn test_token_bucket() {
+    let now = Instant::now();
+    let mut bucket = TokenBucket::per_minute(60.0, now);
+
+    assert_eq!(bucket.wait_time(60.0), None);
+    bucket.add(-60.0);
+    assert_eq!(bucket.wait_time(1.0), Some(Duration::from_secs(1)));
+    // requests larger than the bucket wait until it's full
+    assert_eq!(bucket.wait_time(100.0), Some(Duration::from_secs(60)));
+
+    bucket.refill(now + Duration::from_secs(30));
+    assert_eq!(bucket.wait_time(30.0), None);
+    bucket.refill(now + Duration::from_secs(120));
+    assert_eq!(bucket.available, 60.0);
+}
+
+#[tokio::test]
+async fn test_interactive_requests_go_first() {
+    let limiter = Arc::new(RateLimiter::new(
+        RateLimits::new().with_max_concurrent_requests(1),
+    ));
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    let permit = limiter.acquire(Priority::Interactive, 0).await;
+    let mut waiters = Vec::new();
+    for priority in [Priority::Background, Priority::Interactive] {
+        let limiter = limiter.clone();
+        let order = order.clone();
+        waiters.push(tokio::spawn(async move {
+            let _permit = limiter.acquire(priority, 0).await;
+            order.lock().unwrap().push(priority);
+        }));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let depth = limiter.queue_depth();
+    assert_eq!(
+        (depth.interactive, depth.background, depth.in_flight),
+        (1, 1, 1)
+    );
+
+    drop(permit);
+    for waiter in waiters {
+        waiter.await.unwrap();
+    }
+
+    let order = order.lock().unwrap();
+    assert_eq!(*order, [Priority::Interactive, Priority::Background]);
+    assert_eq!(limiter.queue_depth(), QueueDepth::default());
+}
@@ -0,0 +1,158 @@
+use crate::{LlmError, LlmResult};
+use rand::Rng;
+use std::{future::Future, time::Duration};
+use tokio::time::Instant;
+
+/// Describes how failed requests are retried.
+///
+/// Only transient failures are retried: rate limiting, server errors and transport errors.
+/// Delay between attempts grows exponentially with random jitter, unless the provider tells
+/// how long to wait with the `Retry-After` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            deadline: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that makes a single attempt without a deadline.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            deadline: None,
+            ..Default::default()
+        }
+    }
+
+    /// Set the maximum number of attempts (including the first one). Default is 3.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the delay before the first retry. Default is 500ms.
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Set the upper bound of the delay between attempts. Default is 10s.
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Set the factor the delay is multiplied by after every attempt. Default is 2.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Set the time limit for the whole call including all retries. Default is 60s.
+    pub fn with_deadline(mut self, deadline: impl Into<Option<Duration>>) -> Self {
+        self.deadline = deadline.into();
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Delay before the attempt following the given one (starting from 1), without jitter.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        self.initial_backoff
+            .mul_f64(factor.min(u32::MAX as f64))
+            .min(self.max_backoff)
+    }
+
+    /// Delay before the attempt following the given one, using "equal jitter":
+    /// a random value between half of the backoff and the full backoff.
+    fn jittered_backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        backoff / 2 + backoff.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+    }
+
+    /// Run the request, retrying it according to the policy.
+    /// `name` is used to identify the request in logs.
+    pub async fn run<T, F, Fut>(&self, name: &str, mut request: F) -> LlmResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = LlmResult<T>>,
+    {
+        let deadline = self.deadline.map(|deadline| Instant::now() + deadline);
+        let mut attempt = 1;
+
+        loop {
+            let result = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, request())
+                    .await
+                    .unwrap_or(Err(LlmError::DeadlineExceeded { attempts: attempt })),
+                None => request().await,
+            };
+
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(error) if attempt >= self.max_attempts || !error.is_transient() => {
+                    return Err(error)
+                }
+                Err(error) => error,
+            };
+
+            let delay = error
+                .retry_after()
+                .unwrap_or_else(|| self.jittered_backoff(attempt));
+
+            if let Some(deadline) = deadline {
+                if Instant::now() + delay >= deadline {
+                    log::warn!(
+                        "{name} failed on attempt {attempt}, no time left to retry: {error}"
+                    );
+                    return Err(error);
+                }
+            }
+
+            log::warn!(
+                "{name} failed on attempt {attempt}/{}, retrying in {delay:?}: {error}",
+                self.max_attempts
+            );
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[test]
+fn test_backoff() {
+    let policy = RetryPolicy::default()
+        .with_initial_backoff(Duration::from_millis(100))
+        .with_max_backoff(Duration::from_millis(1000));
+
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(4), Duration::from_millis(800));
+    assert_eq!(policy.backoff(5), Duration::from_millis(1000));
+    assert_eq!(policy.backoff(100), Duration::from_millis(1000));
+
+    for attempt in 1..10 {
+        let backoff = policy.jittered_backoff(attempt);
+        assert!(backoff >= policy.backoff(attempt) / 2);
+        assert!(backoff <= policy.backoff(attempt));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{ChatMessage, ChatRequest, Completion, LlmClient, LlmClientExt, LlmResult, Role};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Messages exchanged with the model in a conversation.
+///
+/// Sessions are independent of clients, so a single client can hold any number of
+/// conversations. Sessions are serializable and can be kept in a [`SessionStore`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatSession {
+    messages: Vec<ChatMessage>,
+    /// When the last message was added.
+    updated_at: SystemTime,
+}
+
+impl Default for ChatSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChatSession {
+    pub fn new() -> Self {
+        Self {
+            messages: Vec::new(),
+            updated_at: SystemTime::now(),
+        }
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn updated_at(&self) -> SystemTime {
+        self.updated_at
+    }
+
+    /// Whether nothing was added to the session for longer than the timeout.
+    pub fn is_expired(&self, idle_timeout: Duration) -> bool {
+        self.updated_at
+            .elapsed()
+            .is_ok_and(|idle| idle > idle_timeout)
+    }
+
+    /// Add a message to the end of the conversation.
+    pub fn push(&mut self, message: ChatMessage) {
+        self.messages.push(message);
+        self.updated_at = SystemTime::now();
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.updated_at = SystemTime::now();
+    }
+
+    /// Keep only the last messages, so the conversation still starts with a user message.
+    pub fn keep_last(&mut self, max_messages: usize) {
+        let mut start = self.messages.len().saturating_sub(max_messages);
+        while start < self.messages.len() && self.messages[start].role != Role::User {
+            start += 1;
+        }
+        self.messages.drain(..start);
+    }
+
+    /// Add messages of the session and the new message to the end of the request.
+    pub fn request(&self, request: ChatRequest, message: ChatMessage) -> ChatRequest {
+        let mut request = request;
+        request.messages.extend(self.messages.iter().cloned());
+        request.with_message(message)
+    }
+
+    /// Send a user message with the conversation so far, after the messages of `request`
+    /// (e.g. the system prompt). The message and the reply are added to the session
+    /// if the request succeeds.
+    pub async fn send<C: LlmClient + ?Sized>(
+        &mut self,
+        client: &C,
+        request: ChatRequest,
+        message: impl ToString,
+    ) -> LlmResult<Completion> {
+        let message = ChatMessage::user(message);
+        let completion = client
+            .complete(self.request(request, message.clone()))
+            .await?;
+
+        self.push(message);
+        self.push(completion.message.clone());
+
+        Ok(completion)
+    }
+}
+
+/// Chat sessions by key (e.g. chat id), kept in memory and optionally saved to a directory,
+/// so conversations survive restarts. Sessions idle for too long are started over.
+///
+/// Keys are used as file names, so they must not contain path separators.
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, ChatSession>>,
+    dir: Option<PathBuf>,
+    idle_timeout: Option<Duration>,
+    max_messages: usize,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self {
+            sessions: Default::default(),
+            dir: None,
+            idle_timeout: Some(Duration::from_secs(30 * 60)),
+            max_messages: 20,
+        }
+    }
+
+    /// Also save sessions to the directory, one file per key.
+    pub fn with_dir(mut self, dir: impl Into<Option<PathBuf>>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Set how long a session can be idle before it's started over. Default is 30 minutes.
+    pub fn with_idle_timeout(mut self, idle_timeout: impl Into<Option<Duration>>) -> Self {
+        self.idle_timeout = idle_timeout.into();
+        self
+    }
+
+    /// Set how many of the last messages of a session are kept. Default is 20.
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages;
+        self
+    }
+
+    /// Session of the key, a new one if there is none or it has expired.
+    pub fn load(&self, key: impl Display) -> ChatSession {
+        let key = key.to_string();
+
+        let session = self
+            .sessions()
+            .get(&key)
+            .cloned()
+            .or_else(|| self.read(&key));
+
+        session
+            .filter(|session| !self.is_expired(session))
+            .unwrap_or_default()
+    }
+
+    /// Replace the session of the key. Sessions that fail to be written to disk are still
+    /// kept in memory.
+    pub fn save(&self, key: impl Display, session: &ChatSession) {
+        let key = key.to_string();
+        let mut session = session.clone();
+        session.keep_last(self.max_messages);
+
+        if let Some(path) = self.path(&key) {
+            let content = serde_json::to_string(&session).expect("session is serializable");
+            let result = self
+                .dir
+                .as_ref()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&path, content));
+            if let Err(e) = result {
+                log::warn!("Failed to save chat session {path:?}: {e}");
+            }
+        }
+
+        self.sessions().insert(key, session);
+    }
+
+    pub fn remove(&self, key: impl Display) {
+        let key = key.to_string();
+        self.sessions().remove(&key);
+
+        if let Some(path) = self.path(&key) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Failed to remove chat session {path:?}: {e}");
+                }
+            }
+        }
+    }
+
+    /// Remove expired sessions from memory and disk. Returns the number of removed sessions.
+    pub fn remove_expired(&self) -> usize {
+        let mut keys = self
+            .sessions()
+            .iter()
+            .filter(|(_, session)| self.is_expired(session))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        let files = self
+            .dir
+            .as_ref()
+            .and_then(|dir| std::fs::read_dir(dir).ok());
+        for entry in files.into_iter().flatten().flatten() {
+            let path = entry.path();
+            let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if !keys.iter().any(|k| k == key) && self.read(key).is_some_and(|s| self.is_expired(&s))
+            {
+                keys.push(key.to_string());
+            }
+        }
+
+        for key in &keys {
+            self.remove(key);
+        }
+
+        keys.len()
+    }
+
+    fn is_expired(&self, session: &ChatSession) -> bool {
+        self.idle_timeout
+            .is_some_and(|timeout| session.is_expired(timeout))
+    }
+
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, ChatSession>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.dir.as_ref()?.join(format!("{key}.json")))
+    }
+
+    fn read(&self, key: &str) -> Option<ChatSession> {
+        let path = self.path(key)?;
+        let content = std::fs::read_to_string(&path).ok()?;
+
+        match serde_json::from_str(&content) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                log::warn!("Invalid chat session {path:?}: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[test]
+fn test_keep_last_messages() {
+    let mut session = ChatSession::new();
+    for i in 0..3 {
+        session.push(ChatMessage::user(format!("question {i}")));
+        session.push(ChatMessage::assistant(format!("answer {i}")));
+    }
+
+    session.keep_last(3);
+
+    let contents = session
+        .messages()
+        .iter()
+        .map(|message| message.content.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(contents, ["question 2", "answer 2"]);
+}
@@ -0,0 +1,64 @@
+/// Incremental parser for `text/event-stream` bodies.
+///
+/// Bytes are fed as they arrive from the network; complete events are returned as soon as the
+/// blank line terminating them is received. Only `data` fields are collected, which is all the
+/// chat completion APIs use.
+#[derive(Debug, Default)]
+pub(crate) struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+/// Payload sent by the server after the last chunk of a stream.
+pub(crate) const SSE_DONE: &str = "[DONE]";
+
+impl SseParser {
+    /// Feed a chunk of the response body and return the data of every completed event.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=pos).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+                continue;
+            }
+
+            // Lines starting with a colon are comments (used by some servers as keep-alive).
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            if field == "data" {
+                self.data
+                    .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+        }
+
+        events
+    }
+}
+
+#[test]
+fn test_sse_parser() {
+    let mut parser = SseParser::default();
+
+    assert!(parser.push(b"data: {\"a\":").is_empty());
+    assert_eq!(
+        parser.push(b"1}\n\n: ping\n\ndata: x\r\n"),
+        vec!["{\"a\":1}"]
+    );
+    assert_eq!(
+        parser.push(b"data: y\r\n\r\ndata: [DONE]\n\n"),
+        vec!["x\ny", SSE_DONE]
+    );
+}
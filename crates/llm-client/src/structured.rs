@@ -0,0 +1,36 @@
+use serde::de::DeserializeOwned;
+
+/// Deserialize a JSON reply of the model.
+/// Markdown code fences around the JSON are ignored, as some models add them even in JSON mode.
+pub fn parse_structured<T: DeserializeOwned>(content: &str) -> serde_json::Result<T> {
+    let content = content.trim();
+    let content = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|content| content.strip_suffix("```"))
+        .unwrap_or(content);
+
+    serde_json::from_str(content.trim())
+}
+
+#[test]
+fn test_parse_structured() {
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Reply {
+        task: String,
+    }
+
+    let expected = Reply {
+        task: "note".to_string(),
+    };
+
+    assert_eq!(
+        parse_structured::<Reply>(r#" {"task": "note"} "#).unwrap(),
+        expected
+    );
+    assert_eq!(
+        parse_structured::<Reply>("```json\n{\"task\": \"note\"}\n```").unwrap(),
+        expected
+    );
+    assert!(parse_structured::<Reply>(r#"{"tags": []}"#).is_err());
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Tool the model can call, described by a JSON schema of its arguments.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Tool {
+    Function { function: FunctionDefinition },
+}
+
+impl Tool {
+    /// Create a function tool.
+    /// `parameters` is a JSON schema object describing the arguments of the function.
+    pub fn function(name: impl ToString, description: impl ToString, parameters: Value) -> Self {
+        Self::Function {
+            function: FunctionDefinition {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters,
+            },
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Function { function } => &function.name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+/// Whether and how the model should call tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// Model decides whether to call tools. Default when tools are provided.
+    Auto,
+    /// Model must not call tools.
+    None,
+    /// Model must call at least one tool.
+    Required,
+}
+
+/// Tool call requested by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(default = "function_type", rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// Arguments encoded as a JSON object.
+    pub arguments: String,
+}
+
+impl FunctionCall {
+    /// Deserialize the arguments of the call.
+    pub fn parse_arguments<T: serde::de::DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(&self.arguments)
+    }
+}
+
+/// Executes tools called by the model, see [`crate::LlmClientExt::run_tools`].
+#[async_trait::async_trait]
+pub trait ToolExecutor: Send + Sync {
+    /// Tools offered to the model.
+    fn tools(&self) -> Vec<Tool>;
+
+    /// Run the call and return the result passed back to the model.
+    /// Errors are passed to the model too, so it can fix the arguments or give up.
+    async fn call(&self, call: &FunctionCall) -> Result<String, String>;
+}
+
+#[test]
+fn test_tool_serde() {
+    use crate::ChatMessage;
+
+    let tool = Tool::function(
+        "save_note",
+        "Save a note",
+        serde_json::json!({
+            "type": "object",
+            "properties": { "text": { "type": "string" } },
+            "required": ["text"],
+        }),
+    );
+    assert_eq!(
+        serde_json::to_value(&tool).unwrap()["function"]["name"],
+        "save_note"
+    );
+
+    let message: ChatMessage = serde_json::from_str(
+        r#"{
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "D681PevKs",
+                "function": { "name": "save_note", "arguments": "{\"text\": \"milk\"}" }
+            }]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(message.content, "");
+    assert_eq!(message.tool_calls[0].function.name, "save_note");
+
+    let reply = ChatMessage::tool(&message.tool_calls[0], "saved");
+    let json = serde_json::to_value(&reply).unwrap();
+    assert_eq!(json["role"], "tool");
+    assert_eq!(json["tool_call_id"], "D681PevKs");
+    assert_eq!(json["name"], "save_note");
+}
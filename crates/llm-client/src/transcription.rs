@@ -0,0 +1,153 @@
+use crate::{error_for_status, LlmError, LlmResult, RetryPolicy};
+use reqwest::multipart::{Form, Part};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+/// Audio file to transcribe.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AudioFile {
+    /// Name of the file, providers use its extension to detect the format.
+    pub file_name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl AudioFile {
+    pub fn new(file_name: impl ToString, mime_type: impl ToString, data: Vec<u8>) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            mime_type: mime_type.to_string(),
+            data,
+        }
+    }
+}
+
+impl Debug for AudioFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AudioFile")
+            .field("file_name", &self.file_name)
+            .field("mime_type", &self.mime_type)
+            .field("size", &self.data.len())
+            .finish()
+    }
+}
+
+/// Text recognized in an audio file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub text: String,
+    /// Language of the speech, if the provider detected it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// Client able to turn speech into text.
+#[async_trait::async_trait]
+pub trait Transcriber: Debug + Send + Sync {
+    async fn transcribe(&self, audio: AudioFile) -> LlmResult<Transcript>;
+}
+
+/// Client for APIs implementing OpenAI `/v1/audio/transcriptions`, such as OpenAI Whisper
+/// or a local whisper.cpp server started with `--inference-path /v1/audio/transcriptions`.
+#[derive(Debug, Clone)]
+pub struct WhisperClient {
+    api_key: Option<String>,
+    api_url: String,
+    client: reqwest::Client,
+    model: String,
+    language: Option<String>,
+    retry_policy: RetryPolicy,
+}
+
+impl WhisperClient {
+    /// Create a client for the API with the given base URL (e.g. `http://localhost:8080/v1`).
+    /// The `/audio/transcriptions` path is appended to it.
+    pub fn new(api_url: impl ToString) -> Self {
+        Self {
+            api_key: None,
+            api_url: api_url.to_string().trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            model: "whisper-1".to_string(),
+            language: None,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Set the API key sent as a bearer token. Local servers usually don't need one.
+    pub fn with_api_key(mut self, api_key: impl Into<Option<String>>) -> Self {
+        self.api_key = api_key.into();
+        self
+    }
+
+    /// Set the model. Default is `whisper-1`, whisper.cpp ignores it.
+    pub fn with_model(mut self, model: impl ToString) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    /// Set the language of the speech as an ISO-639-1 code (e.g. `en`).
+    /// Default is detected by the model.
+    pub fn with_language(mut self, language: impl Into<Option<String>>) -> Self {
+        self.language = language.into();
+        self
+    }
+
+    /// Use the HTTP client, e.g. one built from [`crate::HttpSettings`] and shared with other
+    /// clients. Default is a client with reqwest defaults.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the policy used to retry failed requests. Default is [`RetryPolicy::default`].
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    fn build_form(&self, audio: &AudioFile) -> LlmResult<Form> {
+        let file = Part::bytes(audio.data.clone())
+            .file_name(audio.file_name.clone())
+            .mime_str(&audio.mime_type)?;
+
+        let mut form = Form::new()
+            .part("file", file)
+            .text("model", self.model.clone())
+            .text("response_format", "json");
+        if let Some(language) = &self.language {
+            form = form.text("language", language.clone());
+        }
+
+        Ok(form)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transcriber for WhisperClient {
+    async fn transcribe(&self, audio: AudioFile) -> LlmResult<Transcript> {
+        log::debug!("Sending transcription request: {audio:?}");
+
+        let url = format!("{}/audio/transcriptions", self.api_url);
+        let str_resp = self
+            .retry_policy
+            .run("Transcription request", || async {
+                let mut request = self.client.post(&url).multipart(self.build_form(&audio)?);
+                if let Some(api_key) = &self.api_key {
+                    request = request.bearer_auth(api_key);
+                }
+
+                let response = error_for_status(request.send().await?).await?;
+
+                LlmResult::Ok(response.text().await?)
+            })
+            .await?;
+
+        let transcript: Transcript =
+            serde_json::from_str(&str_resp).map_err(|e| LlmError::malformed(e, &str_resp))?;
+
+        Ok(Transcript {
+            text: transcript.text.trim().to_string(),
+            ..transcript
+        })
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ops::{Add, AddAssign},
+    str::FromStr,
+};
+
+/// Tokens used by a request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    /// Always zero for embeddings.
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+impl Add for Usage {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.prompt_tokens += rhs.prompt_tokens;
+        self.completion_tokens += rhs.completion_tokens;
+        self.total_tokens += rhs.total_tokens;
+    }
+}
+
+/// Rough number of tokens in the text, models use about 4 chars per token for English.
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Price of a model in currency units (e.g. USD) per million tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    pub fn new(prompt: f64, completion: f64) -> Self {
+        Self { prompt, completion }
+    }
+
+    /// Cost of the usage.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
+}
+
+/// Prices of models by model id.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the price of the model.
+    pub fn with_price(mut self, model: impl ToString, price: ModelPrice) -> Self {
+        self.prices.insert(model.to_string(), price);
+        self
+    }
+
+    pub fn price(&self, model: &str) -> Option<ModelPrice> {
+        self.prices.get(model).copied()
+    }
+
+    /// Cost of the usage, `None` if the price of the model is unknown.
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        self.price(model).map(|price| price.cost(usage))
+    }
+}
+
+/// Parse a price table from a comma separated list of `model=prompt_price:completion_price`,
+/// e.g. `mistral-tiny=0.25:0.25,mistral-small=2:6`.
+impl FromStr for PriceTable {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut table = Self::new();
+
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parse = || {
+                let (model, prices) = entry.split_once('=')?;
+                let (prompt, completion) = prices.split_once(':')?;
+                let price =
+                    ModelPrice::new(prompt.trim().parse().ok()?, completion.trim().parse().ok()?);
+
+                Some((model.trim(), price))
+            };
+
+            let (model, price) = parse().ok_or_else(|| {
+                format!("invalid price {entry:?}, expected `model=prompt_price:completion_price`")
+            })?;
+            table = table.with_price(model, price);
+        }
+
+        Ok(table)
+    }
+}
+
+#[test]
+fn test_price_table() {
+    let table: PriceTable = "mistral-tiny=0.25:0.25, mistral-small=2:6".parse().unwrap();
+    let usage = Usage {
+        prompt_tokens: 1_000_000,
+        completion_tokens: 500_000,
+        total_tokens: 1_500_000,
+    };
+
+    assert_eq!(table.cost("mistral-small", &usage), Some(5.0));
+    assert_eq!(table.cost("mistral-tiny", &usage), Some(0.375));
+    assert_eq!(table.cost("mistral-large", &usage), None);
+
+    assert!("".parse::<PriceTable>().is_ok());
+    assert!("mistral-tiny=0.25".parse::<PriceTable>().is_err());
+}
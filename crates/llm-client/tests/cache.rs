@@ -0,0 +1,82 @@
+use fake_llm_server::{FakeLlmServer, ScriptedReply};
+use llm_client::{CachingClient, ChatRequest, LlmClient, MistralClient, RetryPolicy};
+use std::{sync::Arc, time::Duration};
+
+fn mistral(server: &FakeLlmServer) -> Arc<dyn LlmClient> {
+    Arc::new(
+        MistralClient::new("token")
+            .with_api_url(server.api_url())
+            .with_retry_policy(RetryPolicy::none()),
+    )
+}
+
+fn request(text: &str) -> ChatRequest {
+    ChatRequest::new()
+        .with_random_seed(123)
+        .with_user_message(text)
+}
+
+#[tokio::test]
+async fn test_cache_deterministic_requests() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::content("first"),
+        ScriptedReply::content("second"),
+        ScriptedReply::content("third"),
+        ScriptedReply::content("fourth"),
+    ]);
+    let client = CachingClient::new(mistral(&server));
+
+    let miss = client.chat(request("Hi")).await.unwrap();
+    let hit = client.chat(request("Hi")).await.unwrap();
+    assert_eq!(hit.message, miss.message);
+    assert_eq!(hit.usage.total_tokens, 0);
+
+    let bypass = client
+        .chat(request("Hi").with_cache_bypass(true))
+        .await
+        .unwrap();
+    assert_eq!(bypass.message.content, "second");
+
+    let other = client.chat(request("Hello")).await.unwrap();
+    assert_eq!(other.message.content, "third");
+
+    // without a seed the model may reply differently every time
+    let random = ChatRequest::new().with_user_message("Hi");
+    let random = client.chat(random).await.unwrap();
+    assert_eq!(random.message.content, "fourth");
+
+    let stats = client.stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 2));
+}
+
+#[tokio::test]
+async fn test_cache_persistence_and_ttl() {
+    let dir = std::env::temp_dir().join(format!("llm-cache-test-{}", std::process::id()));
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::content("first"),
+        ScriptedReply::content("second"),
+    ]);
+
+    let client = CachingClient::new(mistral(&server)).with_dir(dir.clone());
+    client.chat(request("Hi")).await.unwrap();
+
+    // new client reads completions saved by the previous one
+    let client = CachingClient::new(mistral(&server)).with_dir(dir.clone());
+    let hit = client.chat(request("Hi")).await.unwrap();
+    assert_eq!(hit.message.content, "first");
+
+    let client = CachingClient::new(mistral(&server))
+        .with_dir(dir.clone())
+        .with_ttl(Duration::ZERO);
+    let expired = client.chat(request("Hi")).await.unwrap();
+    assert_eq!(expired.message.content, "second");
+
+    // the file saved with the new completion is expired right away too
+    assert_eq!(client.remove_expired(), 1);
+    assert_eq!(client.stats().entries, 0);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,94 @@
+use fake_llm_server::{FakeLlmServer, ScriptedReply};
+use llm_client::{
+    estimate_prompt_tokens, ChatRequest, LlmClientExt, LlmError, MistralClient, RetryPolicy,
+    TruncationStrategy,
+};
+
+fn client(server: &FakeLlmServer) -> MistralClient {
+    MistralClient::new("token")
+        .with_api_url(server.api_url())
+        .with_retry_policy(RetryPolicy::none())
+}
+
+/// Article of about 40k tokens, larger than the 32k window of `mistral-tiny`.
+fn long_article() -> String {
+    "Some sentence of a long article.\n".repeat(5000)
+}
+
+#[tokio::test]
+async fn test_short_request_is_unchanged() {
+    let server = FakeLlmServer::start().await;
+    let request = ChatRequest::new().with_user_message("Hi");
+
+    let fitted = client(&server)
+        .fit_context(request.clone(), TruncationStrategy::SummarizeChunks)
+        .await
+        .unwrap();
+
+    assert_eq!(fitted, request);
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_truncate_middle() {
+    let server = FakeLlmServer::start().await;
+    let request = ChatRequest::new()
+        .with_system_message("Be nice")
+        .with_user_message(long_article());
+
+    let fitted = client(&server)
+        .fit_context(request, TruncationStrategy::TruncateMiddle)
+        .await
+        .unwrap();
+
+    let tokens = estimate_prompt_tokens(&fitted.messages);
+    assert!((28_000..32_000).contains(&tokens), "{tokens}");
+    assert!(fitted.messages[1].content.contains("[...]"));
+}
+
+#[tokio::test]
+async fn test_summarize_chunks() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::content("First summary"),
+        ScriptedReply::content("Second summary"),
+        ScriptedReply::content("Third summary"),
+    ]);
+    let request = ChatRequest::new()
+        .with_random_seed(1)
+        .with_user_message(long_article());
+
+    let fitted = client(&server)
+        .fit_context(request, TruncationStrategy::SummarizeChunks)
+        .await
+        .unwrap();
+
+    // chunks are summarized concurrently, so the replies may come in any order
+    let mut summaries = fitted.messages[0].content.split("\n\n").collect::<Vec<_>>();
+    summaries.sort();
+    assert_eq!(
+        summaries,
+        ["First summary", "Second summary", "Third summary"]
+    );
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0]["random_seed"], 1);
+}
+
+#[tokio::test]
+async fn test_system_prompt_too_long() {
+    let server = FakeLlmServer::start().await;
+    let request = ChatRequest::new()
+        .with_system_message(long_article())
+        .with_user_message("Hi");
+
+    let error = client(&server)
+        .fit_context(request, TruncationStrategy::DropOldestHistory)
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(error, LlmError::ContextWindowExceeded { .. }),
+        "{error:?}"
+    );
+}
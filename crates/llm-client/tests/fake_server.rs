@@ -0,0 +1,271 @@
+use fake_llm_server::{FakeLlmServer, ScriptedReply};
+use futures::TryStreamExt;
+use llm_client::{
+    ChatRequest, FinishReason, LengthPolicy, LlmClient, LlmClientExt, LlmError, MistralClient,
+    RetryPolicy, StreamEvent,
+};
+use std::time::Duration;
+
+fn client(server: &FakeLlmServer) -> MistralClient {
+    let retry_policy = RetryPolicy::default()
+        .with_initial_backoff(Duration::from_millis(10))
+        .with_deadline(Duration::from_secs(5));
+
+    MistralClient::new("token")
+        .with_api_url(server.api_url())
+        .with_retry_policy(retry_policy)
+}
+
+#[tokio::test]
+async fn test_chat() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::content("Hello there"));
+
+    let request = ChatRequest::new()
+        .with_system_message("Be nice")
+        .with_user_message("Hi");
+    let completion = client(&server).chat(request).await.unwrap();
+
+    assert_eq!(completion.message.content, "Hello there");
+    assert_eq!(completion.usage.completion_tokens, 2);
+
+    let requests = server.requests();
+    assert_eq!(requests[0]["model"], "mistral-tiny");
+    assert_eq!(requests[0]["messages"][1]["content"], "Hi");
+}
+
+#[tokio::test]
+async fn test_retry_transient_errors() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::rate_limited(0),
+        ScriptedReply::ServerError,
+        ScriptedReply::content("ok"),
+    ]);
+
+    let reply = client(&server)
+        .send_message_without_history("Hi")
+        .await
+        .unwrap();
+
+    assert_eq!(reply, "ok");
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn test_give_up_after_max_attempts() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([ScriptedReply::ServerError, ScriptedReply::ServerError]);
+
+    let client = client(&server);
+    let client = client.with_retry_policy(RetryPolicy::default().with_max_attempts(2));
+    let error = client.send_message_without_history("Hi").await.unwrap_err();
+
+    assert!(matches!(error, LlmError::Server { .. }), "{error:?}");
+    assert_eq!(server.pending_replies(), 0);
+}
+
+#[tokio::test]
+async fn test_invalid_responses() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::MalformedJson,
+        ScriptedReply::EmptyChoices,
+        ScriptedReply::truncated("Half of the"),
+    ]);
+    let client = client(&server);
+
+    let error = client.send_message_without_history("Hi").await.unwrap_err();
+    assert!(
+        matches!(error, LlmError::MalformedResponse { .. }),
+        "{error:?}"
+    );
+
+    let error = client.send_message_without_history("Hi").await.unwrap_err();
+    assert!(matches!(error, LlmError::EmptyChoices), "{error:?}");
+
+    let error = client.send_message_without_history("Hi").await.unwrap_err();
+    assert!(
+        matches!(&error, LlmError::Truncated { partial } if partial == "Half of the"),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_deadline() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::content("late").delayed(Duration::from_secs(2)));
+
+    let client = client(&server)
+        .with_retry_policy(RetryPolicy::none().with_deadline(Duration::from_millis(100)));
+    let error = client.send_message_without_history("Hi").await.unwrap_err();
+
+    assert!(
+        matches!(error, LlmError::DeadlineExceeded { .. }),
+        "{error:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_stream() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::content("Hello there, friend"));
+
+    let events: Vec<StreamEvent> = client(&server)
+        .stream_message_without_history("Hi")
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+
+    let content = events
+        .iter()
+        .filter_map(|event| match event {
+            StreamEvent::Delta(delta) => Some(delta.as_str()),
+            StreamEvent::Usage(_) | StreamEvent::Truncated => None,
+        })
+        .collect::<String>();
+    assert_eq!(content, "Hello there, friend");
+    assert!(matches!(events.last(), Some(StreamEvent::Usage(_))));
+    assert_eq!(server.requests()[0]["stream"], true);
+}
+
+#[tokio::test]
+async fn test_validate_model() {
+    let server = FakeLlmServer::start().await;
+    server.set_models(["mistral-tiny", "mistral-small"]);
+    let client = client(&server);
+
+    client.validate_model(Some("mistral-small")).await.unwrap();
+
+    let error = client
+        .validate_model(Some("mistral-large"))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, LlmError::UnknownModel { .. }), "{error:?}");
+}
+
+#[tokio::test]
+async fn test_optional_parameters() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([ScriptedReply::content("a"), ScriptedReply::content("b")]);
+    let client = client(&server);
+
+    client.send_message_without_history("Hi").await.unwrap();
+    let request = ChatRequest::new()
+        .with_top_p(0.9)
+        .with_stop("[[END]]")
+        .with_presence_penalty(0.5)
+        .with_frequency_penalty(0.25)
+        .with_n(2)
+        .with_safe_prompt(true)
+        .with_user_message("Hi")
+        .with_assistant_prefix("Hello");
+    client.chat(request).await.unwrap();
+
+    let requests = server.requests();
+    let parameters = [
+        "max_tokens",
+        "random_seed",
+        "top_p",
+        "stop",
+        "presence_penalty",
+        "frequency_penalty",
+        "n",
+        "safe_prompt",
+    ];
+    for parameter in parameters {
+        assert!(requests[0].get(parameter).is_none(), "{parameter} is set");
+    }
+
+    assert_eq!(requests[1]["top_p"], 0.9);
+    assert_eq!(requests[1]["stop"][0], "[[END]]");
+    assert_eq!(requests[1]["presence_penalty"], 0.5);
+    assert_eq!(requests[1]["frequency_penalty"], 0.25);
+    assert_eq!(requests[1]["n"], 2);
+    assert_eq!(requests[1]["safe_prompt"], true);
+    assert_eq!(requests[1]["messages"][1]["prefix"], true);
+}
+
+#[tokio::test]
+async fn test_length_policy() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::truncated("Half"),
+        ScriptedReply::content("Whole reply"),
+        ScriptedReply::truncated("Half"),
+    ]);
+    let client = client(&server);
+    let request = ChatRequest::new()
+        .with_max_tokens(10)
+        .with_user_message("Hi");
+
+    let completion = client
+        .complete(
+            request
+                .clone()
+                .with_length_policy(LengthPolicy::Retry { max_retries: 1 }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(completion.content(), "Whole reply");
+    assert_eq!(completion.finish_reason, FinishReason::Stop);
+    assert_eq!(completion.id.as_deref(), Some("fake"));
+    assert_eq!(server.requests()[1]["max_tokens"], 20);
+
+    let completion = client
+        .complete(request.with_length_policy(LengthPolicy::Partial))
+        .await
+        .unwrap();
+    assert_eq!(completion.content(), "Half");
+    assert!(completion.is_truncated());
+}
+
+#[tokio::test]
+async fn test_length_retry_with_client_max_tokens() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::truncated("Half"),
+        ScriptedReply::content("Whole reply"),
+    ]);
+    let client = client(&server).with_max_tokens(10);
+    let request = ChatRequest::new()
+        .with_length_policy(LengthPolicy::Retry { max_retries: 1 })
+        .with_user_message("Hi");
+
+    let completion = client.complete(request).await.unwrap();
+    assert_eq!(completion.content(), "Whole reply");
+    assert_eq!(server.requests()[0]["max_tokens"], 10);
+    assert_eq!(server.requests()[1]["max_tokens"], 20);
+}
+
+#[tokio::test]
+async fn test_stream_length_policy() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::truncated("Half"),
+        ScriptedReply::truncated("Half"),
+    ]);
+    let client = client(&server);
+    let request = ChatRequest::new().with_user_message("Hi");
+
+    let events: Vec<StreamEvent> = client
+        .chat_stream(request.clone().with_length_policy(LengthPolicy::Partial))
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(events.contains(&StreamEvent::Truncated));
+    assert!(events.contains(&StreamEvent::Delta("Half".to_string())));
+
+    let error = client
+        .chat_stream(request)
+        .await
+        .unwrap()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap_err();
+    assert!(matches!(error, LlmError::Truncated { .. }), "{error:?}");
+}
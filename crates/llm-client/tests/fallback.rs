@@ -0,0 +1,116 @@
+use fake_llm_server::{FakeLlmServer, ScriptedReply};
+use llm_client::{
+    ChatRequest, CircuitBreakerPolicy, CircuitState, FallbackClient, LlmClient, LlmClientExt,
+    LlmError, MistralClient, OpenAiCompatClient, RetryPolicy,
+};
+use std::{sync::Arc, time::Duration};
+
+fn fallback_client(primary: &FakeLlmServer, secondary: &FakeLlmServer) -> FallbackClient {
+    let primary = MistralClient::new("token")
+        .with_api_url(primary.api_url())
+        .with_retry_policy(RetryPolicy::none());
+    let secondary = OpenAiCompatClient::new(secondary.api_url())
+        .with_model("local")
+        .with_retry_policy(RetryPolicy::none());
+
+    FallbackClient::new("mistral", Arc::new(primary))
+        .with_provider("local", Arc::new(secondary))
+        .with_circuit_breaker(
+            CircuitBreakerPolicy::default()
+                .with_failure_threshold(2)
+                .with_open_duration(Duration::from_millis(200)),
+        )
+}
+
+#[tokio::test]
+async fn test_failover_and_recovery() {
+    let primary = FakeLlmServer::start().await;
+    let secondary = FakeLlmServer::start().await;
+    let client = fallback_client(&primary, &secondary);
+
+    primary.push_replies([
+        ScriptedReply::ServerError,
+        ScriptedReply::rate_limited(None),
+    ]);
+    secondary.push_replies([
+        ScriptedReply::content("first"),
+        ScriptedReply::content("second"),
+        ScriptedReply::content("third"),
+    ]);
+
+    let request = ChatRequest::new()
+        .with_model("mistral-small")
+        .with_user_message("Hi");
+    let reply = client.chat(request).await.unwrap();
+    assert_eq!(reply.message.content, "first");
+    // model names of the primary provider are not sent to the others
+    assert_eq!(secondary.requests()[0]["model"], "local");
+
+    let reply = client.send_message_without_history("Hi").await.unwrap();
+    assert_eq!(reply, "second");
+    assert_eq!(client.health()[0].state, CircuitState::Open);
+
+    // the primary provider is skipped while its circuit is open
+    let reply = client.send_message_without_history("Hi").await.unwrap();
+    assert_eq!(reply, "third");
+    assert_eq!(primary.requests().len(), 2);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(client.health()[0].state, CircuitState::HalfOpen);
+
+    primary.push_reply(ScriptedReply::content("recovered"));
+    let reply = client.send_message_without_history("Hi").await.unwrap();
+    assert_eq!(reply, "recovered");
+    assert_eq!(client.health()[0].state, CircuitState::Closed);
+}
+
+#[tokio::test]
+async fn test_no_failover_on_invalid_output() {
+    let primary = FakeLlmServer::start().await;
+    let secondary = FakeLlmServer::start().await;
+    let client = fallback_client(&primary, &secondary);
+
+    primary.push_reply(ScriptedReply::truncated("cut"));
+
+    let error = client.send_message_without_history("Hi").await.unwrap_err();
+    assert!(matches!(error, LlmError::Truncated { .. }), "{error:?}");
+    assert!(secondary.requests().is_empty());
+    assert_eq!(client.health()[0].consecutive_failures, 0);
+}
+
+#[tokio::test]
+async fn test_single_probe_of_half_open_provider() {
+    let primary = FakeLlmServer::start().await;
+    let secondary = FakeLlmServer::start().await;
+    let client = fallback_client(&primary, &secondary);
+
+    primary.push_replies([ScriptedReply::ServerError, ScriptedReply::ServerError]);
+    secondary.push_replies([
+        ScriptedReply::content("first"),
+        ScriptedReply::content("second"),
+    ]);
+    for _ in 0..2 {
+        client.send_message_without_history("Hi").await.unwrap();
+    }
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(client.health()[0].state, CircuitState::HalfOpen);
+
+    primary.push_reply(ScriptedReply::content("recovered").delayed(Duration::from_millis(100)));
+    secondary.push_replies([
+        ScriptedReply::content("third"),
+        ScriptedReply::content("fourth"),
+    ]);
+
+    // only one of the concurrent requests checks the recovering provider
+    let (first, second, third) = tokio::join!(
+        client.send_message_without_history("Hi"),
+        client.send_message_without_history("Hi"),
+        client.send_message_without_history("Hi"),
+    );
+    let mut replies = [first.unwrap(), second.unwrap(), third.unwrap()];
+    replies.sort();
+    assert_eq!(replies, ["fourth", "recovered", "third"]);
+    assert_eq!(primary.requests().len(), 3);
+    assert_eq!(client.health()[0].state, CircuitState::Closed);
+}
@@ -0,0 +1,63 @@
+use fake_llm_server::{FakeLlmServer, ScriptedReply};
+use llm_client::{ChatRequest, ChatSession, MistralClient, RetryPolicy, SessionStore};
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_session_sends_history() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([
+        ScriptedReply::content("Hi, Bob"),
+        ScriptedReply::content("Your name is Bob"),
+    ]);
+    let client = MistralClient::new("token")
+        .with_api_url(server.api_url())
+        .with_retry_policy(RetryPolicy::none());
+    let request = ChatRequest::new().with_system_message("Be nice");
+
+    let mut session = ChatSession::new();
+    session
+        .send(&client, request.clone(), "I'm Bob")
+        .await
+        .unwrap();
+    let reply = session
+        .send(&client, request, "What's my name?")
+        .await
+        .unwrap();
+    assert_eq!(reply.content(), "Your name is Bob");
+    assert_eq!(session.messages().len(), 4);
+
+    let requests = server.requests();
+    let contents = requests[1]["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|message| message["content"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        contents,
+        ["Be nice", "I'm Bob", "Hi, Bob", "What's my name?"]
+    );
+}
+
+#[test]
+fn test_session_store_persistence_and_expiry() {
+    let dir = std::env::temp_dir().join(format!("llm-session-test-{}", std::process::id()));
+    let mut session = ChatSession::new();
+    session.push(llm_client::ChatMessage::user("Hi"));
+
+    SessionStore::new().with_dir(dir.clone()).save(42, &session);
+
+    // new store reads sessions saved by the previous one
+    let store = SessionStore::new().with_dir(dir.clone());
+    assert_eq!(store.load(42), session);
+    assert!(store.load(43).is_empty());
+
+    let store = SessionStore::new()
+        .with_dir(dir.clone())
+        .with_idle_timeout(Duration::ZERO);
+    assert!(store.load(42).is_empty());
+    assert_eq!(store.remove_expired(), 1);
+    assert!(!dir.join("42.json").exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
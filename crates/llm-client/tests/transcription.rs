@@ -0,0 +1,40 @@
+use fake_llm_server::{FakeLlmServer, ScriptedReply};
+use llm_client::{AudioFile, RetryPolicy, Transcriber, WhisperClient};
+use std::time::Duration;
+
+fn whisper(server: &FakeLlmServer) -> WhisperClient {
+    let retry_policy = RetryPolicy::default().with_initial_backoff(Duration::from_millis(10));
+
+    WhisperClient::new(server.api_url())
+        .with_language("en".to_string())
+        .with_retry_policy(retry_policy)
+}
+
+fn voice() -> AudioFile {
+    AudioFile::new("voice.ogg", "audio/ogg", b"OggS".to_vec())
+}
+
+#[tokio::test]
+async fn test_transcribe() {
+    let server = FakeLlmServer::start().await;
+    server.push_reply(ScriptedReply::content(" Buy milk and bread. "));
+
+    let transcript = whisper(&server).transcribe(voice()).await.unwrap();
+    assert_eq!(transcript.text, "Buy milk and bread.");
+
+    let requests = server.transcription_requests();
+    let body = String::from_utf8_lossy(&requests[0]);
+    assert!(body.contains(r#"filename="voice.ogg""#), "{body}");
+    assert!(body.contains("whisper-1"), "{body}");
+    assert!(body.contains(r#"name="language""#), "{body}");
+}
+
+#[tokio::test]
+async fn test_transcribe_retries_server_errors() {
+    let server = FakeLlmServer::start().await;
+    server.push_replies([ScriptedReply::ServerError, ScriptedReply::content("Hello")]);
+
+    let transcript = whisper(&server).transcribe(voice()).await.unwrap();
+    assert_eq!(transcript.text, "Hello");
+    assert_eq!(server.transcription_requests().len(), 2);
+}